@@ -0,0 +1,641 @@
+//! Reachability bitmaps, stored next to a pack as a .bitmap file. Each one lists
+//! the objects a commit reaches, so enumerating history doesn't mean walking
+//! every tree in it.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    cache::ObjectCache,
+    pack::Index,
+    parsing::{self, Type},
+    store::{ObjectFormat, ObjectStore, Oid},
+    utils::{self, GITLINK},
+    Error,
+};
+
+const SIGNATURE: &[u8] = b"BITM";
+const VERSION: u16 = 1;
+
+/// Every object a bitmapped commit reaches is in the pack
+const OPT_FULL_DAG: u16 = 0x1;
+/// A hash of the path each object was found under follows the bitmaps
+const OPT_HASH_CACHE: u16 = 0x4;
+/// A table for finding commits without reading every bitmap follows them
+const OPT_LOOKUP_TABLE: u16 = 0x10;
+
+/// Besides the tips of history, one commit in this many gets a bitmap
+const SELECT_INTERVAL: usize = 100;
+
+/// A set of bit positions
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Bitmap {
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    pub fn set(&mut self, bit: usize) {
+        let word = bit / 64;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (bit % 64);
+    }
+
+    pub fn get(&self, bit: usize) -> bool {
+        self.words
+            .get(bit / 64)
+            .is_some_and(|word| word & 1 << (bit % 64) != 0)
+    }
+
+    /// Adds every bit of `other`
+    pub fn or(&mut self, other: &Self) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    fn xor(&mut self, other: &Self) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word ^= other;
+        }
+    }
+
+    /// Number of bits set
+    pub fn count(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// The bits that are set, lowest first
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(index, &word)| {
+            (0..64)
+                .filter(move |bit| word & 1 << bit != 0)
+                .map(move |bit| index * 64 + bit)
+        })
+    }
+
+    /// Writes the bitmap compressed as EWAH, like git: runs of empty or full words
+    /// are stored as a count in a marker word, followed by the words in between
+    pub fn write_ewah(&self, output: &mut Vec<u8>) {
+        let len = self
+            .words
+            .iter()
+            .rposition(|&word| word != 0)
+            .map_or(0, |last| last + 1);
+        let words = &self.words[..len];
+
+        // each marker has a bit for whether the run is full, 32 bits of run
+        // length, and 31 bits counting the literal words after the run
+        let mut encoded = vec![];
+        let mut marker;
+        let mut at = 0;
+        loop {
+            marker = encoded.len();
+            let fill = match words.get(at) {
+                Some(&u64::MAX) => u64::MAX,
+                _ => 0,
+            };
+            let run = words[at..]
+                .iter()
+                .take(u32::MAX as usize)
+                .take_while(|&&word| word == fill)
+                .count();
+            at += run;
+            let literals = words[at..]
+                .iter()
+                .take(0x7fff_ffff)
+                .take_while(|&&word| word != 0 && word != u64::MAX)
+                .count();
+
+            encoded.push((fill & 1) | (run as u64) << 1 | (literals as u64) << 33);
+            encoded.extend_from_slice(&words[at..at + literals]);
+            at += literals;
+            if at == words.len() {
+                break;
+            }
+        }
+
+        output.extend_from_slice(&((len * 64) as u32).to_be_bytes());
+        output.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+        for word in encoded {
+            output.extend_from_slice(&word.to_be_bytes());
+        }
+        output.extend_from_slice(&(marker as u32).to_be_bytes());
+    }
+
+    /// Reads an EWAH-compressed bitmap, returning it and how many bytes it took
+    pub fn read_ewah(data: &[u8]) -> crate::Result<(Self, usize)> {
+        let len = ewah_len(data)?;
+        // the header gives the bitmap's size, which no run can take it past
+        let max_words = (read_u32(data, 0) as usize).div_ceil(64);
+        let count = read_u32(data, 4) as usize;
+
+        let mut bitmap = Self::default();
+        let mut words = data[8..8 + count * 8]
+            .chunks(8)
+            .map(|word| u64::from_be_bytes(word.try_into().expect("8 bytes")));
+        while let Some(marker) = words.next() {
+            let fill = if marker & 1 == 0 { 0 } else { u64::MAX };
+            let run = (marker >> 1 & 0xffff_ffff) as usize;
+            let literals = (marker >> 33) as usize;
+
+            crate::ensure!(
+                run + literals <= max_words - bitmap.words.len(),
+                CorruptPack,
+                "EWAH bitmap is longer than its size"
+            );
+            bitmap.words.resize(bitmap.words.len() + run, fill);
+            for _ in 0..literals {
+                bitmap.words.push(
+                    words
+                        .next()
+                        .ok_or_else(|| Error::CorruptPack("EWAH bitmap is truncated".into()))?,
+                );
+            }
+        }
+
+        Ok((bitmap, len))
+    }
+}
+
+/// Size in bytes of the EWAH bitmap at the start of `data`
+fn ewah_len(data: &[u8]) -> crate::Result<usize> {
+    crate::ensure!(data.len() >= 8, CorruptPack, "EWAH bitmap is truncated");
+    let len = 8 + read_u32(data, 4) as usize * 8 + 4;
+    crate::ensure!(data.len() >= len, CorruptPack, "EWAH bitmap is truncated");
+    Ok(len)
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().expect("4 bytes"))
+}
+
+/// The index positions of a pack's objects in the order they're stored in the
+/// pack, which is the order of the bits in its bitmaps, and the bit for each
+/// index position
+fn pack_order(index: &Index) -> crate::Result<(Vec<usize>, Vec<u32>)> {
+    let mut offsets = (0..index.len())
+        .map(|position| Ok((index.offset(position)?, position)))
+        .collect::<crate::Result<Vec<_>>>()?;
+    offsets.sort_unstable();
+
+    let order = offsets
+        .into_iter()
+        .map(|(_, position)| position)
+        .collect::<Vec<_>>();
+    let mut bits = vec![0; order.len()];
+    for (bit, &position) in order.iter().enumerate() {
+        bits[position] = bit as u32;
+    }
+
+    Ok((order, bits))
+}
+
+/// Every object reachable from `roots`, found through the store's bitmaps if it
+/// has any, and otherwise by walking history and trees
+pub fn reachable(store: &dyn ObjectStore, roots: &[Oid]) -> crate::Result<BTreeSet<Oid>> {
+    match store.bitmap()? {
+        Some(bitmap) => bitmap.reachable(store, roots),
+        None => Ok(utils::reachable_objects(store, roots, &BTreeSet::new())?
+            .into_iter()
+            .map(|(oid, _)| oid)
+            .collect()),
+    }
+}
+
+/// The .bitmap file of a pack
+pub struct PackBitmap {
+    index: Index,
+    /// The index position of each bit's object
+    order: Vec<usize>,
+    /// The bit of each index position's object
+    bits: Vec<u32>,
+    data: Vec<u8>,
+    /// Where each bitmapped commit's bitmap starts, and how many commits back
+    /// the bitmap it was XORed with is, if any
+    entries: Vec<(usize, usize)>,
+    commits: HashMap<Oid, usize>,
+}
+
+impl PackBitmap {
+    /// Reads the bitmap of the pack at `pack`, if it has one
+    pub fn open(pack: &Path, format: ObjectFormat) -> crate::Result<Option<Self>> {
+        let path = pack.with_extension("bitmap");
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        let index = Index::open(pack.with_extension("idx"), format)?;
+
+        Self::parse(data, index)
+            .map(Some)
+            .map_err(|error| Error::in_file(&path, error))
+    }
+
+    /// Parses the bitmap of the pack that `index` belongs to
+    pub fn parse(data: Vec<u8>, index: Index) -> crate::Result<Self> {
+        let hash_len = index.pack_checksum().len();
+        let header_len = 12 + hash_len;
+        crate::ensure!(
+            data.len() >= header_len + hash_len && data.starts_with(SIGNATURE),
+            CorruptPack,
+            "invalid bitmap signature"
+        );
+        let version = u16::from_be_bytes([data[4], data[5]]);
+        crate::ensure!(version == VERSION, "unsupported bitmap version {version}");
+        let options = u16::from_be_bytes([data[6], data[7]]);
+        crate::ensure!(
+            options & OPT_FULL_DAG != 0
+                && options & !(OPT_FULL_DAG | OPT_HASH_CACHE | OPT_LOOKUP_TABLE) == 0,
+            CorruptPack,
+            "unsupported bitmap options {options:#x}"
+        );
+        crate::ensure!(
+            data[12..header_len] == *index.pack_checksum(),
+            CorruptPack,
+            "bitmap does not match its pack"
+        );
+
+        // the bitmaps of each type come first, which walks don't need
+        let end = data.len() - hash_len;
+        let mut at = header_len;
+        for _ in 0..4 {
+            at += ewah_len(&data[at..end])?;
+        }
+
+        let count = read_u32(&data, 8) as usize;
+        let mut entries = Vec::with_capacity(count);
+        let mut commits = HashMap::with_capacity(count);
+        for entry in 0..count {
+            crate::ensure!(at + 6 <= end, CorruptPack, "bitmap entries are truncated");
+            let position = read_u32(&data, at) as usize;
+            let xor = data[at + 4] as usize;
+            crate::ensure!(
+                position < index.len() && xor <= entry,
+                CorruptPack,
+                "invalid bitmap entry {entry}"
+            );
+            at += 6;
+            entries.push((at, xor));
+            commits.insert(index.oid(position), entry);
+            at += ewah_len(&data[at..end])?;
+        }
+
+        let (order, bits) = pack_order(&index)?;
+        Ok(Self {
+            index,
+            order,
+            bits,
+            data,
+            entries,
+            commits,
+        })
+    }
+
+    /// Number of objects in the pack
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// The bit of an object, if it's in the pack
+    pub fn bit(&self, oid: &Oid) -> Option<usize> {
+        self.index
+            .position(oid)
+            .map(|position| self.bits[position] as usize)
+    }
+
+    pub fn oid(&self, bit: usize) -> Oid {
+        self.index.oid(self.order[bit])
+    }
+
+    /// The objects a commit reaches, if it has a bitmap
+    pub fn commit(&self, oid: &Oid) -> crate::Result<Option<Bitmap>> {
+        let Some(&entry) = self.commits.get(oid) else {
+            return Ok(None);
+        };
+
+        // bitmaps can be stored XORed with an earlier one, which may be XORed in turn
+        let mut chain = vec![entry];
+        let mut entry = entry;
+        while self.entries[entry].1 != 0 {
+            entry -= self.entries[entry].1;
+            chain.push(entry);
+        }
+        let mut bitmap = Bitmap::default();
+        for entry in chain.into_iter().rev() {
+            let (at, _) = self.entries[entry];
+            bitmap.xor(&Bitmap::read_ewah(&self.data[at..])?.0);
+        }
+
+        Ok(Some(bitmap))
+    }
+
+    /// Every object reachable from `roots`. Bitmapped commits add what they reach
+    /// at once, so only history newer than them and the objects outside the pack
+    /// are walked.
+    pub fn reachable(
+        &self,
+        store: &dyn ObjectStore,
+        roots: &[Oid],
+    ) -> crate::Result<BTreeSet<Oid>> {
+        let cache = ObjectCache::open(store)?;
+        let mut found = Bitmap::default();
+        let mut outside = BTreeSet::new();
+        let mut stack = roots.iter().map(|oid| (*oid, None)).collect::<Vec<_>>();
+
+        while let Some((oid, r#type)) = stack.pop() {
+            let bit = self.bit(&oid);
+            match bit {
+                Some(bit) if found.get(bit) => continue,
+                None if outside.contains(&oid) => continue,
+                _ => {}
+            }
+            if let Some(reached) = self.commit(&oid)? {
+                found.or(&reached);
+                continue;
+            }
+            match bit {
+                Some(bit) => found.set(bit),
+                None => {
+                    outside.insert(oid);
+                }
+            }
+
+            let r#type = match r#type {
+                Some(r#type) => r#type,
+                None => {
+                    store
+                        .read_header(&oid)?
+                        .ok_or_else(|| Error::ObjectNotFound(oid.to_string()))?
+                        .r#type
+                }
+            };
+            match r#type {
+                Type::Blob => {}
+                Type::Tree => {
+                    for entry in cache
+                        .tree(&oid)?
+                        .iter()
+                        .filter(|entry| entry.mode != GITLINK)
+                    {
+                        let r#type = if entry.tree { Type::Tree } else { Type::Blob };
+                        stack.push((entry.hash, Some(r#type)));
+                    }
+                }
+                Type::Commit => {
+                    // parents go on top, so that their bitmaps cover as much
+                    // of this commit's tree as possible before it's walked
+                    let commit = cache.commit(&oid)?;
+                    stack.push((commit.tree, Some(Type::Tree)));
+                    stack.extend(
+                        commit
+                            .parents
+                            .iter()
+                            .map(|parent| (*parent, Some(Type::Commit))),
+                    );
+                }
+                Type::Tag => {
+                    let (_, tag) = parsing::parse_tag(&store.get(&oid)?.contents)
+                        .map_err(|error| Error::corrupt(oid, error))?;
+                    stack.push((tag.object, Some(tag.r#type)));
+                }
+            }
+        }
+
+        Ok(found
+            .iter()
+            .map(|bit| self.oid(bit))
+            .chain(outside)
+            .collect())
+    }
+}
+
+/// Writes bitmaps for the pack that `index` belongs to, which must contain every
+/// object reachable from `roots`, and returns how many commits got one. The tips of
+/// history get bitmaps, and so does one commit in every hundred of the rest.
+pub fn write_bitmap(
+    store: &dyn ObjectStore,
+    index: &Index,
+    roots: &[Oid],
+    mut output: impl Write,
+) -> crate::Result<usize> {
+    let (order, bits) = pack_order(index)?;
+    let bit = |oid: &Oid| {
+        index
+            .position(oid)
+            .map(|position| bits[position] as usize)
+            .ok_or_else(|| Error::Other(format!("object {oid} is reachable but not in the pack")))
+    };
+    let cache = ObjectCache::open(store)?;
+
+    // commits, trees, blobs and tags, in the order git stores them
+    let mut types: [Bitmap; 4] = Default::default();
+    let type_slot = |r#type| match r#type {
+        Type::Commit => 0,
+        Type::Tree => 1,
+        Type::Blob => 2,
+        Type::Tag => 3,
+    };
+
+    // walking everything first finds the type of each object, and checks that
+    // the pack is complete, since bitmaps can't point outside of it
+    let mut seen = Bitmap::default();
+    let mut commits = HashMap::new();
+    let mut stack = roots.iter().map(|oid| (*oid, None)).collect::<Vec<_>>();
+    while let Some((oid, r#type)) = stack.pop() {
+        let bit = bit(&oid)?;
+        if seen.get(bit) {
+            continue;
+        }
+        seen.set(bit);
+
+        let r#type = match r#type {
+            Some(r#type) => r#type,
+            None => store.get(&oid)?.r#type,
+        };
+        types[type_slot(r#type)].set(bit);
+        match r#type {
+            Type::Blob => {}
+            Type::Tree => {
+                for entry in cache
+                    .tree(&oid)?
+                    .iter()
+                    .filter(|entry| entry.mode != GITLINK)
+                {
+                    let r#type = if entry.tree { Type::Tree } else { Type::Blob };
+                    stack.push((entry.hash, Some(r#type)));
+                }
+            }
+            Type::Commit => {
+                let commit = cache.commit(&oid)?;
+                stack.push((commit.tree, Some(Type::Tree)));
+                stack.extend(
+                    commit
+                        .parents
+                        .iter()
+                        .map(|parent| (*parent, Some(Type::Commit))),
+                );
+                commits.insert(oid, commit);
+            }
+            Type::Tag => {
+                let (_, tag) = parsing::parse_tag(&store.get(&oid)?.contents)
+                    .map_err(|error| Error::corrupt(oid, error))?;
+                stack.push((tag.object, Some(tag.r#type)));
+            }
+        }
+    }
+    for (bit, &position) in order.iter().enumerate() {
+        if !seen.get(bit) {
+            let oid = index.oid(position);
+            types[type_slot(store.get(&oid)?.r#type)].set(bit);
+        }
+    }
+
+    // newest first, so that tips are easy to pick, then oldest first,
+    // so that each bitmap can reuse the ones of the commits it reaches
+    let mut selected = commits.keys().copied().collect::<Vec<_>>();
+    selected.sort_unstable_by_key(|oid| (std::cmp::Reverse(commits[oid].commit_time), *oid));
+    let parents = commits
+        .values()
+        .flat_map(|commit| commit.parents.iter().copied())
+        .collect::<BTreeSet<_>>();
+    let selected = selected
+        .into_iter()
+        .enumerate()
+        .filter(|(number, oid)| number % SELECT_INTERVAL == 0 || !parents.contains(oid))
+        .map(|(_, oid)| oid)
+        .rev()
+        .collect::<Vec<_>>();
+
+    let mut bitmaps = HashMap::<Oid, Bitmap>::new();
+    for commit in &selected {
+        let mut bitmap = Bitmap::default();
+
+        // history first, then the trees of the commits without bitmaps
+        let mut unmapped = vec![];
+        let mut stack = vec![*commit];
+        while let Some(oid) = stack.pop() {
+            let bit = bit(&oid)?;
+            if bitmap.get(bit) {
+                continue;
+            }
+            if let Some(reached) = bitmaps.get(&oid) {
+                bitmap.or(reached);
+                continue;
+            }
+            bitmap.set(bit);
+            stack.extend(commits[&oid].parents.iter().copied());
+            unmapped.push(commits[&oid].tree);
+        }
+
+        let mut stack = unmapped;
+        while let Some(oid) = stack.pop() {
+            let bit = bit(&oid)?;
+            if bitmap.get(bit) {
+                continue;
+            }
+            bitmap.set(bit);
+            if types[1].get(bit) {
+                for entry in cache
+                    .tree(&oid)?
+                    .iter()
+                    .filter(|entry| entry.mode != GITLINK)
+                {
+                    stack.push(entry.hash);
+                }
+            }
+        }
+
+        bitmaps.insert(*commit, bitmap);
+    }
+
+    let mut file = vec![];
+    file.extend_from_slice(SIGNATURE);
+    file.extend_from_slice(&VERSION.to_be_bytes());
+    file.extend_from_slice(&OPT_FULL_DAG.to_be_bytes());
+    file.extend_from_slice(&(selected.len() as u32).to_be_bytes());
+    file.extend_from_slice(index.pack_checksum());
+    for bitmap in &types {
+        bitmap.write_ewah(&mut file);
+    }
+    for commit in &selected {
+        let position = index.position(commit).expect("commits are in the pack");
+        file.extend_from_slice(&(position as u32).to_be_bytes());
+        // no XOR with an earlier bitmap, and no flags
+        file.extend_from_slice(&[0, 0]);
+        bitmaps[commit].write_ewah(&mut file);
+    }
+
+    let mut hasher = store.format().hasher();
+    hasher.update(&file);
+    output.write_all(&file)?;
+    output.write_all(hasher.finalize().as_bytes())?;
+
+    Ok(selected.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ewah_round_trip() {
+        let mut bitmap = Bitmap::default();
+        for bit in [0, 3, 64, 200]
+            .into_iter()
+            .chain(256..448)
+            .chain([1000, 1001])
+        {
+            bitmap.set(bit);
+        }
+
+        let mut encoded = vec![];
+        bitmap.write_ewah(&mut encoded);
+        let (decoded, len) = Bitmap::read_ewah(&encoded).unwrap();
+        assert_eq!(len, encoded.len());
+        assert_eq!(
+            decoded.iter().collect::<Vec<_>>(),
+            bitmap.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(decoded.count(), 4 + 192 + 2);
+        assert!(decoded.get(300) && !decoded.get(500));
+
+        // runs of empty and full words take no space besides their markers,
+        // so four markers are enough for the four literal words
+        assert_eq!(read_u32(&encoded, 4), 8);
+
+        let mut encoded = vec![];
+        Bitmap::default().write_ewah(&mut encoded);
+        let (empty, _) = Bitmap::read_ewah(&encoded).unwrap();
+        assert_eq!(empty.count(), 0);
+
+        // a run past the size in the header is rejected before it's allocated
+        let mut encoded = 64u32.to_be_bytes().to_vec();
+        encoded.extend_from_slice(&1u32.to_be_bytes());
+        encoded.extend_from_slice(&(u64::from(u32::MAX) << 1).to_be_bytes());
+        encoded.extend_from_slice(&0u32.to_be_bytes());
+        assert!(matches!(
+            Bitmap::read_ewah(&encoded),
+            Err(Error::CorruptPack(_))
+        ));
+    }
+}
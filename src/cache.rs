@@ -0,0 +1,254 @@
+//! Decoded commits and trees, kept in memory so history and tree walks
+//! don't inflate and parse the same objects every time they reach them
+
+use std::{
+    cell::{OnceCell, RefCell},
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    commit_graph::CommitGraph,
+    parsing::{self, Commit, Type},
+    store::{ObjectStore, Oid},
+    utils::{self, Entry},
+    Error, COMMIT_GRAPH, DOT_GIT, INFO, OBJECTS,
+};
+
+#[derive(Clone)]
+enum Decoded {
+    Commit(Rc<Commit>),
+    Tree(Rc<[Entry]>),
+}
+
+/// What history walks need to know about a commit
+pub struct CommitNode {
+    pub parents: Vec<Oid>,
+    pub commit_time: u64,
+}
+
+/// Reads commits and trees through a store, keeping the most recently used ones
+/// until their encoded sizes add up to the limit
+pub struct ObjectCache<'a> {
+    store: &'a dyn ObjectStore,
+    limit: u64,
+    lru: RefCell<Lru>,
+    /// Where to find a commit-graph, which is only read once a walk needs it
+    graph_path: Option<PathBuf>,
+    graph: OnceCell<Option<CommitGraph>>,
+}
+
+#[derive(Default)]
+struct Lru {
+    /// Each object, with its size and when it was last used
+    objects: HashMap<Oid, (Decoded, u64, u64)>,
+    /// Objects by when they were last used, oldest first
+    order: BTreeMap<u64, Oid>,
+    clock: u64,
+    size: u64,
+}
+
+impl<'a> ObjectCache<'a> {
+    /// A cache holding up to `limit` bytes of objects, where 0 disables caching
+    pub fn new(store: &'a dyn ObjectStore, limit: u64) -> Self {
+        Self {
+            store,
+            limit,
+            lru: RefCell::default(),
+            graph_path: None,
+            graph: OnceCell::new(),
+        }
+    }
+
+    /// A cache sized by core.objectCacheLimit, which reads commits from the
+    /// repository's commit-graph when it has one
+    pub fn open(store: &'a dyn ObjectStore) -> crate::Result<Self> {
+        Ok(
+            Self::new(store, utils::object_cache_limit()?).with_commit_graph(
+                Path::new(DOT_GIT)
+                    .join(OBJECTS)
+                    .join(INFO)
+                    .join(COMMIT_GRAPH),
+            ),
+        )
+    }
+
+    /// Reads the parents and dates of commits from this commit-graph file, if it exists
+    pub fn with_commit_graph(mut self, path: impl Into<PathBuf>) -> Self {
+        self.graph_path = Some(path.into());
+        self
+    }
+
+    pub fn store(&self) -> &'a dyn ObjectStore {
+        self.store
+    }
+
+    /// The commit with this id, which has its `hash` set
+    pub fn commit(&self, oid: &Oid) -> crate::Result<Rc<Commit>> {
+        if let Some(Decoded::Commit(commit)) = self.lookup(oid) {
+            return Ok(commit);
+        }
+
+        let object = self.store.get(oid)?;
+        if object.r#type != Type::Commit {
+            return Err(Error::WrongType {
+                oid: *oid,
+                expected: Type::Commit,
+                found: object.r#type,
+            });
+        }
+        let (_, mut commit) =
+            parsing::parse_commit(&object.contents).map_err(|error| Error::corrupt(*oid, error))?;
+        commit.hash = Some(oid.to_string());
+
+        let commit = Rc::new(commit);
+        self.insert(*oid, Decoded::Commit(commit.clone()), object.contents.len());
+        Ok(commit)
+    }
+
+    /// The parents and date of a commit, from the commit-graph if it has the commit,
+    /// and otherwise from the commit itself
+    pub fn commit_node(&self, oid: &Oid) -> crate::Result<CommitNode> {
+        if let Some(graph) = self.commit_graph() {
+            // a commit the graph can't describe is read from the store instead
+            if let Ok(Some(commit)) = graph.get(oid) {
+                return Ok(CommitNode {
+                    parents: commit.parents,
+                    commit_time: commit.commit_time,
+                });
+            }
+        }
+
+        let commit = self.commit(oid)?;
+        Ok(CommitNode {
+            parents: commit.parents.clone(),
+            commit_time: commit.commit_time as u64,
+        })
+    }
+
+    /// The commit-graph, unless it's missing or can't be read, in which case
+    /// commits are read from the store like git does
+    fn commit_graph(&self) -> Option<&CommitGraph> {
+        if let Some(graph) = self.graph.get() {
+            return graph.as_ref();
+        }
+        let graph = self.graph_path.as_ref().and_then(|path| {
+            CommitGraph::open(path, self.store.format()).unwrap_or_else(|error| {
+                eprintln!("warning: ignoring the commit-graph: {error}");
+                None
+            })
+        });
+        self.graph.get_or_init(|| graph).as_ref()
+    }
+
+    /// The entries of the tree with this id
+    pub fn tree(&self, oid: &Oid) -> crate::Result<Rc<[Entry]>> {
+        if let Some(Decoded::Tree(entries)) = self.lookup(oid) {
+            return Ok(entries);
+        }
+
+        let object = self.store.get(oid)?;
+        if object.r#type != Type::Tree {
+            return Err(Error::WrongType {
+                oid: *oid,
+                expected: Type::Tree,
+                found: object.r#type,
+            });
+        }
+        let (_, entries) = parsing::parse_tree(self.store.format())(&object.contents)
+            .map_err(|error| Error::corrupt(*oid, error))?;
+
+        let entries = Rc::<[Entry]>::from(entries);
+        self.insert(*oid, Decoded::Tree(entries.clone()), object.contents.len());
+        Ok(entries)
+    }
+
+    fn lookup(&self, oid: &Oid) -> Option<Decoded> {
+        let mut lru = self.lru.borrow_mut();
+        let lru = &mut *lru;
+        let (decoded, _, used) = lru.objects.get_mut(oid)?;
+        lru.order.remove(used);
+        lru.clock += 1;
+        *used = lru.clock;
+        lru.order.insert(lru.clock, *oid);
+        Some(decoded.clone())
+    }
+
+    fn insert(&self, oid: Oid, decoded: Decoded, size: usize) {
+        let size = size as u64;
+        if self.limit == 0 || size > self.limit {
+            return;
+        }
+
+        let mut lru = self.lru.borrow_mut();
+        while lru.size + size > self.limit {
+            let Some((_, oldest)) = lru.order.pop_first() else {
+                break;
+            };
+            if let Some((_, size, _)) = lru.objects.remove(&oldest) {
+                lru.size -= size;
+            }
+        }
+
+        lru.clock += 1;
+        let clock = lru.clock;
+        lru.order.insert(clock, oid);
+        lru.size += size;
+        if let Some((_, size, used)) = lru.objects.insert(oid, (decoded, size, clock)) {
+            // another walk may have decoded the same object in the meantime
+            lru.order.remove(&used);
+            lru.size -= size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::MemoryStore;
+
+    use super::*;
+
+    #[test]
+    fn evict_least_recently_used() {
+        let store = MemoryStore::default();
+        let tree = store.write(Type::Tree, b"").unwrap();
+        let commit = |message: &str| {
+            let contents = format!(
+                "tree {tree}\nauthor A <a@b.c> 0 +0000\ncommitter A <a@b.c> 0 +0000\n\n{message}"
+            );
+            let oid = store.write(Type::Commit, contents.as_bytes()).unwrap();
+            (oid, contents.len() as u64)
+        };
+        let (first, size) = commit("first");
+        let (second, _) = commit("second");
+        let (third, _) = commit("third");
+
+        // room for two commits, and the empty tree
+        let cache = ObjectCache::new(&store, size * 2 + 1);
+        assert!(cache.tree(&tree).unwrap().is_empty());
+        let first_commit = cache.commit(&first).unwrap();
+        assert_eq!(
+            first_commit.hash.as_deref(),
+            Some(first.to_string().as_str())
+        );
+        cache.commit(&second).unwrap();
+        // using the first commit again makes the second the oldest
+        assert!(Rc::ptr_eq(&first_commit, &cache.commit(&first).unwrap()));
+        cache.commit(&third).unwrap();
+
+        let lru = cache.lru.borrow();
+        assert!(lru.objects.contains_key(&first));
+        assert!(!lru.objects.contains_key(&second));
+        assert!(lru.objects.contains_key(&third));
+        assert!(lru.size <= size * 2 + 1);
+        drop(lru);
+
+        assert!(cache.tree(&first).is_err());
+        let uncached = ObjectCache::new(&store, 0);
+        assert!(!Rc::ptr_eq(
+            &uncached.commit(&first).unwrap(),
+            &uncached.commit(&first).unwrap()
+        ));
+    }
+}
@@ -1,24 +1,43 @@
 pub mod cat_file;
 pub mod clone;
 pub mod commit;
+pub mod commit_graph;
 pub mod commit_tree;
 pub mod config;
+pub mod count_objects;
+pub mod fsck;
+pub mod gc;
 pub mod hash_object;
+pub mod index_pack;
 pub mod init;
 pub mod log;
 pub mod ls_tree;
+pub mod mktag;
+pub mod mktree;
+pub mod pack_objects;
+pub mod prune;
+pub mod rev_parse;
+pub mod unpack_objects;
+pub mod verify_pack;
 pub mod write_tree;
 
 #[cfg(test)]
 mod tests {
-    use crate::FORCE_SINGLE_THREAD;
+    use crate::{
+        store::{Database, MemoryStore, ObjectFormat, ObjectStore, Oid},
+        Error, FORCE_SINGLE_THREAD,
+    };
 
     use super::*;
-    use std::{env, fs, io, path::PathBuf, sync::MutexGuard};
+    use std::{
+        env, fs, io,
+        path::{Path, PathBuf},
+        sync::MutexGuard,
+    };
 
-    const TEST_DIR: &'static str = "test_dir";
+    const TEST_DIR: &str = "test_dir";
 
-    struct Setup(MutexGuard<'static, ()>);
+    struct Setup(#[allow(dead_code)] MutexGuard<'static, ()>);
     impl Setup {
         fn init() -> Self {
             let guard = FORCE_SINGLE_THREAD.lock().unwrap();
@@ -35,11 +54,26 @@ mod tests {
         }
     }
 
+    /// Commits a tree holding a single file with the given contents
+    fn commit_file(store: &dyn ObjectStore, contents: &str, parent: Option<Oid>) -> Oid {
+        use crate::parsing::Type;
+
+        let blob = store.write(Type::Blob, contents.as_bytes()).unwrap();
+        let mut tree = b"100644 lines.txt\0".to_vec();
+        tree.extend(blob.as_bytes());
+        let tree = store.write(Type::Tree, &tree).unwrap();
+        let parent = parent.map_or(String::new(), |parent| format!("parent {parent}\n"));
+        let commit = format!(
+            "tree {tree}\n{parent}author A <a@b.c> 0 +0000\ncommitter A <a@b.c> 0 +0000\n\nmsg\n"
+        );
+        store.write(Type::Commit, commit.as_bytes()).unwrap()
+    }
+
     #[test]
     fn initialize() {
         let _setup = Setup::init();
 
-        init::init(".", io::sink()).unwrap();
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
 
         let mut filenames = fs::read_dir(".git")
             .unwrap()
@@ -60,12 +94,14 @@ mod tests {
         let file = "a.txt";
         const HASH: &str = "dbe9dba55ea8fd4d5be3868b015e044be0848ec5";
 
-        init::init(".", io::sink()).unwrap();
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        let store = Database::open(".git/objects").unwrap();
 
-        fs::write(&file, "Hello, world").unwrap();
+        fs::write(file, "Hello, world").unwrap();
 
         let mut output = vec![];
         hash_object::hash_object(
+            &store,
             true,
             hash_object::Type::Blob,
             hash_object::Source::Path(PathBuf::from(file)),
@@ -86,23 +122,349 @@ mod tests {
         .is_file());
 
         let mut output = vec![];
-        cat_file::cat_file(cat_file::Info::Print, HASH, &mut output).unwrap();
+        cat_file::cat_file(&store, cat_file::Info::Print, HASH, &mut output).unwrap();
         assert_eq!(output, b"Hello, world");
 
         output.clear();
-        cat_file::cat_file(cat_file::Info::Type, HASH, &mut output).unwrap();
+        cat_file::cat_file(&store, cat_file::Info::Type, HASH, &mut output).unwrap();
         assert_eq!(output, b"blob");
 
         output.clear();
-        cat_file::cat_file(cat_file::Info::Size, HASH, &mut output).unwrap();
+        cat_file::cat_file(&store, cat_file::Info::Size, HASH, &mut output).unwrap();
         assert_eq!(output, b"12");
     }
 
+    #[test]
+    fn store_big_file() {
+        let _setup = Setup::init();
+        const HASH: &str = "dbe9dba55ea8fd4d5be3868b015e044be0848ec5";
+
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        let store = Database::open(".git/objects").unwrap();
+        config::config(
+            config::Action::Set("core.bigFileThreshold".into(), "4".into()),
+            io::sink(),
+        )
+        .unwrap();
+
+        fs::write("big.txt", "Hello, world").unwrap();
+
+        let mut output = vec![];
+        hash_object::hash_object(
+            &store,
+            true,
+            hash_object::Type::Blob,
+            hash_object::Source::Path(PathBuf::from("big.txt")),
+            true,
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output, format!("{HASH}\n").as_bytes());
+
+        output.clear();
+        cat_file::cat_file(&store, cat_file::Info::Print, HASH, &mut output).unwrap();
+        assert_eq!(output, b"Hello, world");
+
+        // no temporary files are left in the objects directory
+        let mut filenames = fs::read_dir(".git/objects")
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        filenames.sort();
+        assert_eq!(filenames, ["db"]);
+    }
+
+    #[test]
+    fn store_uncompressed() {
+        let _setup = Setup::init();
+        const HASH: &str = "dbe9dba55ea8fd4d5be3868b015e044be0848ec5";
+
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        config::config(
+            config::Action::Set("core.compression".into(), "0".into()),
+            io::sink(),
+        )
+        .unwrap();
+        let store = Database::open(".git/objects").unwrap();
+
+        fs::write("a.txt", "Hello, world").unwrap();
+        hash_object::hash_object(
+            &store,
+            true,
+            hash_object::Type::Blob,
+            hash_object::Source::Path(PathBuf::from("a.txt")),
+            true,
+            io::sink(),
+        )
+        .unwrap();
+
+        // level 0 stores the contents as-is inside the zlib stream
+        let object = fs::read(Path::new(".git/objects").join(&HASH[..2]).join(&HASH[2..])).unwrap();
+        assert!(object
+            .windows(b"blob 12\0Hello, world".len())
+            .any(|window| window == b"blob 12\0Hello, world"));
+
+        // core.looseCompression takes precedence
+        config::config(
+            config::Action::Set("core.looseCompression".into(), "9".into()),
+            io::sink(),
+        )
+        .unwrap();
+        assert_eq!(
+            crate::utils::loose_compression().unwrap(),
+            flate2::Compression::best()
+        );
+
+        config::config(
+            config::Action::Set("core.compression".into(), "10".into()),
+            io::sink(),
+        )
+        .unwrap();
+        assert!(Database::open(".git/objects").is_err());
+    }
+
+    #[test]
+    fn cat_file_batch() {
+        let store = MemoryStore::default();
+        store
+            .write(crate::parsing::Type::Blob, b"Hello, world")
+            .unwrap();
+        store
+            .write(crate::parsing::Type::Blob, b"hello world")
+            .unwrap();
+
+        let input = "dbe9dba55ea8fd4d5be3868b015e044be0848ec5\n95d09f2 two words\nabc\n0000000\n";
+
+        let mut output = vec![];
+        let batch = cat_file::Batch {
+            contents: true,
+            format: None,
+        };
+        cat_file::cat_file_batch(&store, batch, input.as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+dbe9dba55ea8fd4d5be3868b015e044be0848ec5 blob 12
+Hello, world
+95d09f2 two words missing
+abc missing
+0000000 missing
+"
+        );
+
+        let mut output = vec![];
+        let batch = cat_file::Batch {
+            contents: false,
+            format: Some("%(objecttype) %(objectsize): %(rest)".into()),
+        };
+        cat_file::cat_file_batch(&store, batch, input.as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "blob 12: \nblob 11: two words\nabc missing\n0000000 missing\n"
+        );
+
+        let batch = cat_file::Batch {
+            contents: false,
+            format: Some("%(objectmode)".into()),
+        };
+        assert!(cat_file::cat_file_batch(&store, batch, input.as_bytes(), io::sink()).is_err());
+
+        // these two blobs both start with 8324
+        store.write(crate::parsing::Type::Blob, b"142").unwrap();
+        store.write(crate::parsing::Type::Blob, b"784").unwrap();
+        let mut output = vec![];
+        let batch = cat_file::Batch {
+            contents: false,
+            format: None,
+        };
+        cat_file::cat_file_batch(&store, batch, "8324\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(output, b"8324 ambiguous\n");
+    }
+
+    #[test]
+    fn create_tag() {
+        let store = MemoryStore::default();
+        store
+            .write(crate::parsing::Type::Blob, b"hello world")
+            .unwrap();
+
+        let tag = "\
+object 95d09f2b10159347eece71399a7e2e907ea3df4f
+type blob
+tag v1.0
+tagger A U Thor <author@example.com> 1700000000 +0100
+
+release
+-----BEGIN SSH SIGNATURE-----
+abc
+-----END SSH SIGNATURE-----
+";
+        let mut output = vec![];
+        mktag::mktag(&store, tag.as_bytes(), &mut output).unwrap();
+        assert_eq!(output, b"dc49369bbabacc510943966d6071a99799f072e0\n");
+
+        let (_, parsed) = crate::parsing::parse_tag(tag.as_bytes()).unwrap();
+        assert_eq!(parsed.name, "v1.0");
+        assert_eq!(parsed.tagger.unwrap().timestamp, 1700000000);
+        assert_eq!(parsed.message, "release\n");
+        assert_eq!(
+            parsed.signature.unwrap(),
+            "-----BEGIN SSH SIGNATURE-----\nabc\n-----END SSH SIGNATURE-----\n"
+        );
+
+        let mut output = vec![];
+        cat_file::cat_file(&store, cat_file::Info::Print, "dc49369b", &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), tag);
+
+        // tags must name an existing object of the right type, and have a tagger
+        let wrong_type = tag.replace("type blob", "type commit");
+        assert!(mktag::mktag(&store, wrong_type.as_bytes(), io::sink()).is_err());
+        let missing = tag.replace("95d09f2b", "95d09f2c");
+        assert!(mktag::mktag(&store, missing.as_bytes(), io::sink()).is_err());
+        let no_tagger = tag.replace(
+            "tagger A U Thor <author@example.com> 1700000000 +0100\n",
+            "",
+        );
+        assert!(mktag::mktag(&store, no_tagger.as_bytes(), io::sink()).is_err());
+        let bad_name = tag.replace("v1.0", "v1..0");
+        assert!(mktag::mktag(&store, bad_name.as_bytes(), io::sink()).is_err());
+    }
+
+    #[test]
+    fn check_repository() {
+        let _setup = Setup::init();
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        let store = Database::open(Path::new(".git").join("objects")).unwrap();
+        let fsck = |output: &mut Vec<u8>, errors: &mut Vec<u8>| {
+            fsck::fsck(&store, false, true, output, errors)
+        };
+        let remove = |oid: crate::store::Oid| {
+            let hash = oid.to_string();
+            fs::remove_file(format!(".git/objects/{}/{}", &hash[..2], &hash[2..])).unwrap();
+        };
+
+        let blob = store
+            .write(crate::parsing::Type::Blob, b"hello world")
+            .unwrap();
+        let mut tree = b"100644 hello.txt\0".to_vec();
+        tree.extend(blob.as_bytes());
+        let tree = store.write(crate::parsing::Type::Tree, &tree).unwrap();
+        let commit = format!(
+            "tree {tree}\nauthor A <a@b.c> 0 +0000\ncommitter A <a@b.c> 0 +0000\n\nfirst\n"
+        );
+        let commit = store
+            .write(crate::parsing::Type::Commit, commit.as_bytes())
+            .unwrap();
+        fs::write(".git/refs/heads/master", format!("{commit}\n")).unwrap();
+
+        let (mut output, mut errors) = (vec![], vec![]);
+        fsck(&mut output, &mut errors).unwrap();
+        assert!(output.is_empty() && errors.is_empty());
+
+        let dangling = store
+            .write(crate::parsing::Type::Blob, b"dangling")
+            .unwrap();
+        let mut output = vec![];
+        fsck(&mut output, &mut vec![]).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("dangling blob {dangling}\n")
+        );
+
+        // entries must be in git's order, where "a.txt" comes before the tree "a"
+        let mut unsorted = b"40000 a\0".to_vec();
+        unsorted.extend(tree.as_bytes());
+        unsorted.extend(b"100644 a.txt\0");
+        unsorted.extend(blob.as_bytes());
+        let unsorted = store.write(crate::parsing::Type::Tree, &unsorted).unwrap();
+        let mut errors = vec![];
+        let error = fsck(&mut vec![], &mut errors).unwrap_err();
+        assert!(matches!(error, Error::ChecksFailed { status: 1, .. }));
+        assert_eq!(error.exit_code(), 1);
+        assert_eq!(
+            String::from_utf8(errors).unwrap(),
+            format!("error in tree {unsorted}: treeNotSorted: not properly sorted\n")
+        );
+        remove(unsorted);
+
+        // the file "a" and the tree "a" are apart, but still duplicates
+        let mut duplicate = b"100644 a\0".to_vec();
+        duplicate.extend(blob.as_bytes());
+        duplicate.extend(b"100644 a.txt\0");
+        duplicate.extend(blob.as_bytes());
+        duplicate.extend(b"40000 a\0");
+        duplicate.extend(tree.as_bytes());
+        let duplicate = store.write(crate::parsing::Type::Tree, &duplicate).unwrap();
+        let mut errors = vec![];
+        assert!(fsck(&mut vec![], &mut errors).is_err());
+        assert_eq!(
+            String::from_utf8(errors).unwrap(),
+            format!(
+                "error in tree {duplicate}: duplicateEntries: contains duplicate file entries\n"
+            )
+        );
+        remove(duplicate);
+
+        remove(blob);
+        let mut output = vec![];
+        assert_eq!(fsck(&mut output, &mut vec![]).unwrap_err().exit_code(), 2);
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .starts_with(&format!("missing blob {blob}\n")));
+    }
+
+    #[test]
+    fn make_tree() {
+        use crate::parsing::Type;
+
+        let store = MemoryStore::default();
+        let blob = store.write(Type::Blob, b"hello world").unwrap();
+        let empty = store.write(Type::Tree, b"").unwrap();
+
+        // the tree "a" sorts after "a.txt" and "a-b", even though it's shorter
+        let input = format!(
+            "100644 blob {blob}\tab\n040000 tree {empty}\ta\n\
+             100644 blob {blob}\ta.txt\n100755 blob {blob}\ta-b\n"
+        );
+        let mut output = vec![];
+        mktree::mktree(&store, false, input.as_bytes(), &mut output).unwrap();
+        assert_eq!(output, b"8fe054d00da68aa6c74949fa68273b9144ef3365\n");
+
+        // ls-tree's output works as input, including with NUL terminators
+        let mut entries = vec![];
+        ls_tree::ls_tree(
+            &store,
+            false,
+            false,
+            false,
+            ls_tree::Abbrev::Full,
+            "8fe054d0",
+            &mut entries,
+        )
+        .unwrap();
+        let entries = String::from_utf8(entries).unwrap().replace('\n', "\0");
+        let mut output = vec![];
+        mktree::mktree(&store, true, entries.as_bytes(), &mut output).unwrap();
+        assert_eq!(output, b"8fe054d00da68aa6c74949fa68273b9144ef3365\n");
+
+        let wrong_type = format!("040000 tree {blob}\ta\n");
+        assert!(mktree::mktree(&store, false, wrong_type.as_bytes(), io::sink()).is_err());
+        let missing = format!("100644 blob {empty}\tb\n").replace("4b82", "4b83");
+        assert!(mktree::mktree(&store, false, missing.as_bytes(), io::sink()).is_err());
+        let duplicate = format!("100644 blob {blob}\ta\n100755 blob {blob}\ta\n");
+        assert!(mktree::mktree(&store, false, duplicate.as_bytes(), io::sink()).is_err());
+        // a file and a tree with the same name aren't next to each other in tree order
+        let duplicate =
+            format!("100644 blob {blob}\ta\n100644 blob {blob}\ta.txt\n040000 tree {empty}\ta\n");
+        assert!(mktree::mktree(&store, false, duplicate.as_bytes(), io::sink()).is_err());
+    }
+
     #[test]
     fn store_and_load_tree() {
         let _setup = Setup::init();
 
-        init::init(".", io::sink()).unwrap();
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        let store = Database::open(".git/objects").unwrap();
 
         fs::write("test_file_1.txt", "hello world").unwrap();
         fs::create_dir("test_dir_1").unwrap();
@@ -111,12 +473,21 @@ mod tests {
         fs::write("test_dir_2/test_file_3.txt", "hello world").unwrap();
 
         let mut output = vec![];
-        write_tree::write_tree(&mut output).unwrap();
+        write_tree::write_tree(&store, &mut output).unwrap();
 
         assert_eq!(output, b"1d6753fb1a4263946e82a7ce64b7dcaa3191dfb2\n");
 
         output.clear();
-        ls_tree::ls_tree(true, false, false, 8, "1d675", &mut output).unwrap();
+        ls_tree::ls_tree(
+            &store,
+            true,
+            false,
+            false,
+            ls_tree::Abbrev::Min(16),
+            "1d675",
+            &mut output,
+        )
+        .unwrap();
 
         assert_eq!(
             String::from_utf8(output).unwrap(),
@@ -129,4 +500,733 @@ mod tests {
 "
         );
     }
+
+    #[test]
+    fn store_and_load_tree_in_memory() {
+        let _setup = Setup::init();
+        let store = MemoryStore::default();
+
+        fs::write("test_file_1.txt", "hello world").unwrap();
+        fs::create_dir("test_dir_1").unwrap();
+        fs::write("test_dir_1/test_file_2.txt", "hello world").unwrap();
+
+        let mut output = vec![];
+        write_tree::write_tree(&store, &mut output).unwrap();
+        assert_eq!(store.iter().unwrap().count(), 3);
+
+        output.clear();
+        ls_tree::ls_tree(
+            &store,
+            true,
+            false,
+            true,
+            ls_tree::Abbrev::Full,
+            "c5cc9e",
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "test_dir_1\ntest_file_2.txt\ntest_file_1.txt\n"
+        );
+        assert!(!Path::new(".git").exists());
+    }
+
+    #[test]
+    fn sha256_repository() {
+        let _setup = Setup::init();
+
+        init::init(".", ObjectFormat::Sha256, io::sink()).unwrap();
+        let store = Database::open(".git/objects").unwrap();
+        assert_eq!(store.format(), ObjectFormat::Sha256);
+
+        fs::write("test_file_1.txt", "hello world").unwrap();
+        fs::create_dir("test_dir_1").unwrap();
+        fs::write("test_dir_1/test_file_2.txt", "hello world").unwrap();
+
+        let mut output = vec![];
+        write_tree::write_tree(&store, &mut output).unwrap();
+        assert_eq!(
+            output,
+            b"4438ef9776c741f7089686c645a14e86c04efae9f8e81d4b78abb52dc0484756\n"
+        );
+
+        output.clear();
+        ls_tree::ls_tree(
+            &store,
+            true,
+            false,
+            false,
+            ls_tree::Abbrev::Min(12),
+            "4438ef",
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+040000\ttree\t818baf72e570\ttest_dir_1
+100644\tblob\tfee53a18d328\ttest_file_2.txt
+100644\tblob\tfee53a18d328\ttest_file_1.txt
+"
+        );
+
+        let mut output = vec![];
+        commit_tree::commit_tree(&store, &[], "message", Some("4438ef"), &mut output).unwrap();
+        let commit = String::from_utf8(output).unwrap();
+        assert_eq!(commit.trim().len(), 64);
+
+        let mut output = vec![];
+        log::log(&store, true, Some(&commit), &mut output).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .starts_with(&format!("{} message", &commit[..7])));
+    }
+
+    #[test]
+    fn log_merges() {
+        use crate::parsing::Type;
+
+        let _setup = Setup::init();
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        let store = Database::open(Path::new(".git").join("objects")).unwrap();
+
+        // each merge doubles the paths back to the root, so a walk that
+        // revisits commits would never finish
+        let mut tip = commit_file(&store, "root\n", None);
+        for i in 0..40 {
+            let left = commit_file(&store, &format!("left {i}\n"), Some(tip));
+            let right = commit_file(&store, &format!("right {i}\n"), Some(tip));
+            let merge = format!(
+                "tree {}\nparent {left}\nparent {right}\n\
+                 author A <a@b.c> 0 +0000\ncommitter A <a@b.c> 0 +0000\n\nmerge\n",
+                store.write(Type::Tree, b"").unwrap()
+            );
+            tip = store.write(Type::Commit, merge.as_bytes()).unwrap();
+        }
+
+        let mut output = vec![];
+        log::log(&store, true, Some(&tip.to_string()), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap().lines().count(),
+            1 + 40 * 3
+        );
+    }
+
+    #[test]
+    fn repack_objects() {
+        use crate::parsing::Type;
+
+        let _setup = Setup::init();
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        let store = Database::open(Path::new(".git").join("objects")).unwrap();
+
+        // two versions of a file, which should pack as a blob and a small delta
+        let old = (0..200).map(|i| format!("line {i}\n")).collect::<String>();
+        let new = old.replace("line 100\n", "line one hundred\n");
+        let delta = crate::pack::create_delta(old.as_bytes(), new.as_bytes(), new.len()).unwrap();
+        assert!(delta.len() < 64);
+        assert_eq!(
+            crate::pack::apply_delta(old.as_bytes(), &delta).unwrap(),
+            new.as_bytes()
+        );
+
+        let first = commit_file(&store, &old, None);
+        let second = commit_file(&store, &new, Some(first));
+        fs::write(".git/refs/heads/master", format!("{second}\n")).unwrap();
+        let dangling = store.write(Type::Blob, b"dangling").unwrap();
+
+        let objects = store.iter().unwrap().collect::<Vec<_>>();
+        assert_eq!(objects.len(), 7);
+        gc::gc(&store, crate::pack::DeltaLimits::default()).unwrap();
+
+        // only the unreachable blob is left loose
+        let store = Database::open(Path::new(".git").join("objects")).unwrap();
+        assert_eq!(
+            store.loose().iter().unwrap().collect::<Vec<_>>(),
+            [dangling]
+        );
+        assert_eq!(store.packs().paths().len(), 1);
+        // the pack was streamed to a temporary file, which was renamed into place
+        assert_eq!(fs::read_dir(".git/objects/pack").unwrap().count(), 2);
+        for oid in &objects {
+            let object = store.get(oid).unwrap();
+            assert_eq!(object.oid(ObjectFormat::Sha1), *oid);
+
+            // deltas give their size without being applied
+            let header = store.read_header(oid).unwrap().unwrap();
+            assert_eq!(header.r#type, object.r#type);
+            assert_eq!(header.size, object.contents.len());
+        }
+        let pack_size = fs::metadata(&store.packs().paths()[0]).unwrap().len();
+        assert!(pack_size < old.len() as u64);
+
+        fsck::fsck(&store, false, true, io::sink(), io::sink()).unwrap();
+    }
+
+    #[test]
+    fn prune_objects() {
+        use crate::{parsing::Type, utils::parse_expiry};
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        let _setup = Setup::init();
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        let store = Database::open(Path::new(".git").join("objects")).unwrap();
+
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(parse_expiry("now", now).unwrap(), now);
+        assert_eq!(parse_expiry("never", now).unwrap(), UNIX_EPOCH);
+        assert_eq!(parse_expiry("2.weeks.ago", now).unwrap(), now - 14 * day);
+        assert_eq!(parse_expiry("1 day ago", now).unwrap(), now - day);
+        assert_eq!(
+            parse_expiry("@86400", now).unwrap(),
+            UNIX_EPOCH + Duration::from_secs(86400)
+        );
+        assert!(parse_expiry("soon", now).is_err());
+
+        let blob = store.write(Type::Blob, b"kept").unwrap();
+        let tag = format!("object {blob}\ntype blob\ntag v1\ntagger A <a@b.c> 0 +0000\n");
+        let tag = store.write(Type::Tag, tag.as_bytes()).unwrap();
+        fs::create_dir_all(".git/refs/tags").unwrap();
+        fs::write(".git/refs/tags/v1", format!("{tag}\n")).unwrap();
+
+        let old = store.write(Type::Blob, b"old").unwrap();
+        let hash = old.to_string();
+        // objects are read-only, but their owner can still change their times
+        fs::File::open(format!(".git/objects/{}/{}", &hash[..2], &hash[2..]))
+            .unwrap()
+            .set_modified(now - 30 * day)
+            .unwrap();
+        let recent = store.write(Type::Blob, b"recent").unwrap();
+
+        let mut output = vec![];
+        prune::prune(&store, now - day, true, &mut output).unwrap();
+        assert_eq!(output, format!("{old} blob\n").as_bytes());
+        assert_eq!(store.loose().iter().unwrap().count(), 4);
+
+        prune::prune(&store, now - day, false, io::sink()).unwrap();
+        let mut left = store.loose().iter().unwrap().collect::<Vec<_>>();
+        left.sort();
+        let mut expected = vec![blob, tag, recent];
+        expected.sort();
+        assert_eq!(left, expected);
+    }
+
+    #[test]
+    fn count_objects() {
+        use crate::parsing::Type;
+
+        let _setup = Setup::init();
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        let store = Database::open(Path::new(".git").join("objects")).unwrap();
+        let count = |verbose| {
+            let mut output = vec![];
+            count_objects::count_objects(&store, Path::new(".git/objects"), verbose, &mut output)
+                .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(count(false), "0 objects, 0 kilobytes\n");
+
+        store.write(Type::Blob, b"loose").unwrap();
+        store.write(Type::Blob, b"both").unwrap();
+        let objects = MemoryStore::new(ObjectFormat::Sha1);
+        let packed = [b"both".as_slice(), b"packed"]
+            .map(|contents| (objects.write(Type::Blob, contents).unwrap(), String::new()));
+        store
+            .packs()
+            .write_objects(&objects, &packed, crate::pack::DeltaLimits::default())
+            .unwrap();
+        fs::write(".git/objects/pack/junk", "").unwrap();
+
+        assert!(count(false).starts_with("2 objects, "));
+        let verbose = count(true);
+        let lines = verbose.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "count: 2");
+        assert_eq!(&lines[2..4], ["in-pack: 2", "packs: 1"]);
+        assert_eq!(&lines[5..7], ["prune-packable: 1", "garbage: 1"]);
+    }
+
+    #[test]
+    fn index_and_verify_pack() {
+        use crate::{
+            pack::{self, DeltaLimits},
+            parsing::Type,
+            store::Object,
+        };
+        use flate2::Compression;
+
+        let _setup = Setup::init();
+
+        let old = (0..100).map(|i| format!("line {i}\n")).collect::<String>();
+        let new = format!("{old}line 100\n");
+        let objects = [&old, &new].map(|contents| {
+            let object = Object {
+                r#type: Type::Blob,
+                contents: contents.as_bytes().to_vec(),
+            };
+            (object, "lines.txt".to_owned())
+        });
+        let mut pack = vec![];
+        let (_, checksum) = pack::write_delta_pack(
+            &objects,
+            DeltaLimits::default(),
+            ObjectFormat::Sha1,
+            Compression::default(),
+            &mut pack,
+        )
+        .unwrap();
+        fs::write("test.pack", &pack).unwrap();
+
+        let mut output = vec![];
+        index_pack::index_pack(
+            Path::new("test.pack"),
+            None,
+            ObjectFormat::Sha1,
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output, format!("{checksum}\n").as_bytes());
+
+        let mut output = vec![];
+        verify_pack::verify_pack(Path::new("test.idx"), true, ObjectFormat::Sha1, &mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        let new_oid = objects[1].0.oid(ObjectFormat::Sha1);
+        let old_oid = objects[0].0.oid(ObjectFormat::Sha1);
+        // the larger version is stored whole, and the other as a delta of it
+        assert!(lines[0].starts_with(&format!("{new_oid} blob   {} ", new.len())));
+        assert!(lines[1].starts_with(&format!("{old_oid} blob   ")));
+        assert!(lines[1].ends_with(&format!(" 1 {new_oid}")));
+        assert_eq!(
+            &lines[2..],
+            [
+                "non delta: 1 object",
+                "chain length = 1: 1 object",
+                "test.pack: ok"
+            ]
+        );
+
+        let last = pack.len() - 30;
+        pack[last] ^= 1;
+        fs::write("test.pack", &pack).unwrap();
+        assert!(matches!(
+            index_pack::index_pack(Path::new("test.pack"), None, ObjectFormat::Sha1, io::sink()),
+            Err(Error::CorruptPack(_))
+        ));
+        assert!(matches!(
+            verify_pack::verify_pack(Path::new("test.idx"), false, ObjectFormat::Sha1, io::sink()),
+            Err(Error::CorruptPack(_))
+        ));
+
+        // sizes whose continuation bytes never end are corrupt, not an overflow
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x01\xb0".to_vec();
+        pack.extend([0xff; 16]);
+        let mut hasher = ObjectFormat::Sha1.hasher();
+        hasher.update(&pack);
+        pack.extend(hasher.finalize().as_bytes());
+        assert!(matches!(
+            index_pack::index_entries(&pack, ObjectFormat::Sha1),
+            Err(Error::CorruptPack(_))
+        ));
+        let delta = [0xff; 16];
+        assert!(matches!(
+            pack::apply_delta(b"", &delta),
+            Err(Error::CorruptPack(_))
+        ));
+    }
+
+    #[test]
+    fn unpack_thin_pack() {
+        use crate::parsing::Type;
+        use flate2::{read::ZlibEncoder, Compression};
+        use io::Read;
+        use sha1::{Digest, Sha1};
+
+        let _setup = Setup::init();
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        let store = Database::open(Path::new(".git").join("objects")).unwrap();
+        let base = store.write(Type::Blob, b"hello world").unwrap();
+
+        // a single ref delta, whose base is only in the store
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x01\x76".to_vec();
+        pack.extend(base.as_bytes());
+        ZlibEncoder::new(&[11, 12, 0x90, 11, 1, b'!'][..], Compression::default())
+            .read_to_end(&mut pack)
+            .unwrap();
+        let checksum = Sha1::digest(&pack);
+        pack.extend(checksum);
+
+        unpack_objects::unpack_objects(&store, true, pack.as_slice()).unwrap();
+        assert_eq!(store.loose().iter().unwrap().count(), 1);
+
+        unpack_objects::unpack_objects(&store, false, pack.as_slice()).unwrap();
+        let oid = "bc7774a7b18deb1d7bd0212d34246a9b1260ae17".parse().unwrap(); // "hello world!"
+        assert_eq!(store.get(&oid).unwrap().contents, b"hello world!");
+
+        // the trailing checksum has to match
+        let last = pack.len() - 1;
+        pack[last] ^= 1;
+        assert!(unpack_objects::unpack_objects(&store, false, pack.as_slice()).is_err());
+    }
+
+    #[test]
+    fn pack_objects_from_revisions() {
+        use crate::pack::DeltaLimits;
+
+        let _setup = Setup::init();
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        let store = Database::open(Path::new(".git").join("objects")).unwrap();
+
+        let first = commit_file(&store, "first\n", None);
+        let second = commit_file(&store, "second\n", Some(first));
+        fs::write(".git/refs/heads/master", format!("{second}\n")).unwrap();
+
+        // only the second commit, its tree and its blob are new in the range
+        let mut pack = vec![];
+        let range = format!("{first}..master\n");
+        pack_objects::pack_objects(
+            &store,
+            true,
+            DeltaLimits::default(),
+            false,
+            None,
+            range.as_bytes(),
+            &mut pack,
+        )
+        .unwrap();
+        let unpacked = MemoryStore::default();
+        unpack_objects::unpack_objects(&unpacked, false, pack.as_slice()).unwrap();
+        assert_eq!(unpacked.iter().unwrap().count(), 3);
+        assert!(unpacked.exists(&second).unwrap());
+        assert!(!unpacked.exists(&first).unwrap());
+
+        // objects can also be listed one by one, and packed into files
+        let mut output = vec![];
+        pack_objects::pack_objects(
+            &store,
+            false,
+            DeltaLimits::default(),
+            false,
+            Some(Path::new("listed")),
+            format!("{first}\n{second}\n{first}\n").as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        let checksum = String::from_utf8(output).unwrap();
+        let index = format!("listed-{}.idx", checksum.trim_end());
+        let mut output = vec![];
+        verify_pack::verify_pack(Path::new(&index), true, ObjectFormat::Sha1, &mut output).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("non delta: 2 objects\n"));
+    }
+
+    #[test]
+    fn parse_revisions() {
+        use crate::{parsing::Type, revision};
+
+        let _setup = Setup::init();
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        let store = Database::open(Path::new(".git").join("objects")).unwrap();
+
+        let first = commit_file(&store, "first\n", None);
+        let second = commit_file(&store, "second\n", Some(first));
+        fs::write(".git/refs/heads/main", format!("{second}\n")).unwrap();
+        fs::create_dir_all(".git/refs/heads/topic").unwrap();
+        fs::write(".git/refs/tags/v1", format!("{first}\n")).unwrap();
+        fs::create_dir_all(".git/logs").unwrap();
+        fs::write(
+            ".git/logs/HEAD",
+            format!("{first} {second} A <a@b.c> 0 +0000\tcheckout: moving from v1 to main\n"),
+        )
+        .unwrap();
+
+        for (name, oid) in [
+            ("HEAD", second),
+            ("@", second),
+            ("main~", first),
+            ("HEAD^1", first),
+            ("refs/heads/main^^0", first),
+            ("tags/v1", first),
+            ("@{-1}", first),
+            (&second.to_string()[..7], second),
+        ] {
+            assert_eq!(revision::resolve(&store, name).unwrap(), oid, "{name}");
+        }
+        assert!(matches!(
+            revision::resolve(&store, "HEAD~2"),
+            Err(Error::InvalidRef { .. })
+        ));
+        assert!(matches!(
+            revision::resolve(&store, "topic"),
+            Err(Error::InvalidRef { .. })
+        ));
+
+        let tree = revision::resolve_as(&store, "v1", Type::Tree).unwrap();
+        assert_eq!(revision::resolve(&store, "v1^{tree}").unwrap(), tree);
+        assert!(matches!(
+            revision::resolve(&store, "v1^{blob}"),
+            Err(Error::WrongType {
+                expected: Type::Blob,
+                found: Type::Commit,
+                ..
+            })
+        ));
+
+        let mut output = vec![];
+        cat_file::cat_file(&store, cat_file::Info::Print, "main:lines.txt", &mut output).unwrap();
+        assert_eq!(output, b"second\n");
+
+        assert_eq!(
+            revision::split_range("..main").unwrap(),
+            Some(("HEAD", "main"))
+        );
+        assert_eq!(revision::split_range("HEAD:../lines.txt").unwrap(), None);
+        assert!(matches!(
+            revision::split_range("v1...main"),
+            Err(Error::InvalidRef { .. })
+        ));
+
+        let mut output = vec![];
+        let revisions = ["v1..main".to_owned()];
+        rev_parse::rev_parse(&store, &revisions, ls_tree::Abbrev::Min(7), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "{}\n^{}\n",
+                &second.to_string()[..7],
+                &first.to_string()[..7]
+            )
+        );
+    }
+
+    #[test]
+    fn log_with_commit_graph() {
+        let _setup = Setup::init();
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        let objects = Path::new(".git").join("objects");
+        let store = Database::open(&objects).unwrap();
+
+        let mut tip = commit_file(&store, "0\n", None);
+        for i in 1..5 {
+            tip = commit_file(&store, &format!("{i}\n"), Some(tip));
+        }
+        fs::write(".git/refs/heads/main", format!("{tip}\n")).unwrap();
+
+        let mut expected = vec![];
+        log::log(&store, false, None, &mut expected).unwrap();
+
+        // with every commit in the graph, the walk never parses them,
+        // yet each one shown still has its message and author
+        commit_graph::write(&store, &objects).unwrap();
+        let mut output = vec![];
+        log::log(&store, false, None, &mut output).unwrap();
+        assert_eq!(output, expected);
+        assert_eq!(
+            String::from_utf8(output)
+                .unwrap()
+                .matches("\tmsg\n")
+                .count(),
+            5
+        );
+    }
+
+    #[test]
+    fn write_commit_graph() {
+        use crate::{cache::ObjectCache, commit_graph::CommitGraph};
+
+        let _setup = Setup::init();
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        let objects = Path::new(".git").join("objects");
+        let store = Database::open(&objects).unwrap();
+
+        let first = commit_file(&store, "first\n", None);
+        let second = commit_file(&store, "second\n", Some(first));
+        let third = commit_file(&store, "third\n", Some(second));
+        fs::write(".git/refs/heads/main", format!("{third}\n")).unwrap();
+        commit_graph::write(&store, &objects).unwrap();
+
+        let path = objects.join("info").join("commit-graph");
+        let graph = CommitGraph::open(&path, ObjectFormat::Sha1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(graph.len(), 3);
+        for (generation, (oid, parents)) in [
+            (first, vec![]),
+            (second, vec![first]),
+            (third, vec![second]),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let commit = graph.get(&oid).unwrap().unwrap();
+            assert_eq!(commit.parents, parents);
+            assert_eq!(commit.generation, generation as u32 + 1);
+            assert_eq!(commit.commit_time, 0);
+
+            let node = ObjectCache::open(&store)
+                .unwrap()
+                .commit_node(&oid)
+                .unwrap();
+            assert_eq!(node.parents, parents);
+        }
+        assert!(graph
+            .get(&store.write(crate::parsing::Type::Blob, b"").unwrap())
+            .unwrap()
+            .is_none());
+
+        let mut output = vec![];
+        log::log(&store, true, None, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 3);
+
+        // a graph that doesn't match its checksum is rejected
+        let mut data = fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        fs::write(&path, &data).unwrap();
+        assert!(matches!(
+            CommitGraph::open(&path, ObjectFormat::Sha1),
+            Err(Error::CorruptPack(_))
+        ));
+
+        // a corrupt commit-graph is ignored, and commits are read instead
+        fs::write(&path, b"CGPH").unwrap();
+        let mut output = vec![];
+        log::log(&store, true, None, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn enumerate_with_bitmaps() {
+        use crate::bitmap;
+
+        let _setup = Setup::init();
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+        let store = Database::open(Path::new(".git").join("objects")).unwrap();
+
+        let mut head = None;
+        for number in 0..5 {
+            head = Some(commit_file(&store, &format!("{number}\n"), head));
+        }
+        let packed = head.unwrap();
+        fs::write(".git/refs/heads/main", format!("{packed}\n")).unwrap();
+        config::config(
+            config::Action::Set("repack.writeBitmaps".into(), "true".into()),
+            io::sink(),
+        )
+        .unwrap();
+        gc::gc(&store, crate::pack::DeltaLimits::default()).unwrap();
+
+        let store = Database::open(Path::new(".git").join("objects")).unwrap();
+        let pack = &store.packs().paths()[0];
+        assert!(pack.with_extension("bitmap").exists());
+        let bitmaps = store.bitmap().unwrap().unwrap();
+        assert_eq!(bitmaps.len(), 15);
+        let reached = bitmaps.commit(&packed).unwrap().unwrap();
+        assert_eq!(reached.count(), 15);
+
+        // history newer than the pack is walked, and the rest comes from the bitmap
+        let loose = commit_file(&store, "loose\n", Some(packed));
+        let reachable = bitmap::reachable(&store, &[loose]).unwrap();
+        let walked = crate::utils::reachable_objects(&store, &[loose], &Default::default())
+            .unwrap()
+            .into_iter()
+            .map(|(oid, _)| oid)
+            .collect();
+        assert_eq!(reachable, walked);
+        assert_eq!(reachable.len(), 18);
+
+        // removing the pack removes its bitmaps
+        store.packs().remove_pack(pack).unwrap();
+        assert!(!pack.with_extension("bitmap").exists());
+        assert!(store.bitmap().unwrap().is_none());
+    }
+
+    #[test]
+    fn store_pack_with_deltas() {
+        use flate2::{read::ZlibEncoder, Compression};
+        use io::Read;
+        use sha1::{Digest, Sha1};
+
+        let _setup = Setup::init();
+
+        init::init(".", ObjectFormat::Sha1, io::sink()).unwrap();
+
+        const BASE: &str = "95d09f2b10159347eece71399a7e2e907ea3df4f"; // "hello world"
+        let entry = |header: &[u8], data: &[u8]| {
+            let mut entry = header.to_vec();
+            ZlibEncoder::new(data, Compression::default())
+                .read_to_end(&mut entry)
+                .unwrap();
+            entry
+        };
+
+        // a ref delta whose base comes later in the pack, followed by
+        // an offset delta that uses the first delta as its own base
+        let mut ref_delta_header = vec![0x70 | 6];
+        for i in 0..20 {
+            ref_delta_header.push(u8::from_str_radix(&BASE[i * 2..][..2], 16).unwrap());
+        }
+        let ref_delta = entry(&ref_delta_header, &[11, 12, 0x90, 11, 1, b'!']);
+        let blob = entry(&[0x30 | 11], b"hello world");
+        let distance = (ref_delta.len() + blob.len()) as u8;
+        assert!(distance < 128);
+        let ofs_delta = entry(&[0x60 | 6, distance], &[12, 13, 0x90, 12, 1, b'!']);
+
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x03".to_vec();
+        pack.extend(ref_delta);
+        pack.extend(blob);
+        pack.extend(ofs_delta);
+        let checksum = Sha1::digest(&pack);
+        pack.extend(checksum);
+
+        clone::store_pack(&pack).unwrap();
+        let store = Database::open(".git/objects").unwrap();
+
+        let mut hashes = index_pack::index_entries(&pack, ObjectFormat::Sha1)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.oid)
+            .collect::<Vec<_>>();
+        hashes.sort();
+        let expected = [
+            "362791b037ca504b134f1898a248adc30212fea8", // "hello world!!"
+            BASE,
+            "bc7774a7b18deb1d7bd0212d34246a9b1260ae17", // "hello world!"
+        ];
+        for (oid, expected) in hashes.iter().zip(expected) {
+            assert_eq!(oid.to_string(), expected);
+        }
+
+        let packs = fs::read_dir(".git/objects/pack")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(packs.len(), 2);
+        let index = packs
+            .iter()
+            .find(|path| path.extension().unwrap() == "idx")
+            .unwrap();
+        // header, fanout, 3 * (hash, crc, offset), pack and index checksums
+        assert_eq!(
+            fs::read(index).unwrap().len(),
+            8 + 256 * 4 + 3 * 28 + 2 * 20
+        );
+
+        // every object, including resolved deltas, is readable from the pack
+        let mut output = vec![];
+        cat_file::cat_file(&store, cat_file::Info::Print, "362791", &mut output).unwrap();
+        assert_eq!(output, b"hello world!!");
+
+        output.clear();
+        cat_file::cat_file(&store, cat_file::Info::Type, "bc7774", &mut output).unwrap();
+        assert_eq!(output, b"blob");
+
+        output.clear();
+        cat_file::cat_file(&store, cat_file::Info::Size, BASE, &mut output).unwrap();
+        assert_eq!(output, b"11");
+    }
 }
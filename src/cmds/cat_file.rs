@@ -1,11 +1,6 @@
-use flate2::read::ZlibDecoder;
+use std::io::{BufRead, Write};
 
-use std::{
-    fs,
-    io::{Read, Write},
-};
-
-use crate::{parsing, utils};
+use crate::{parsing, revision, store::ObjectStore, Error};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -13,7 +8,8 @@ pub struct Args {
     pub info: InfoArgs,
 
     /// The object's hash
-    pub hash: String,
+    #[arg(required_unless_present_any = ["batch", "batch_check"])]
+    pub hash: Option<String>,
 }
 
 #[derive(clap::Args)]
@@ -30,6 +26,14 @@ pub struct InfoArgs {
     /// Print the object's contents
     #[arg(short)]
     pub print: bool,
+
+    /// Print the header and contents of each object named on stdin
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true)]
+    pub batch: Option<Option<String>>,
+
+    /// Print the header of each object named on stdin
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true)]
+    pub batch_check: Option<Option<String>>,
 }
 
 pub enum Info {
@@ -38,64 +42,208 @@ pub enum Info {
     Print,
 }
 
-impl From<InfoArgs> for Info {
+/// Options for reading many objects named on stdin
+pub struct Batch {
+    /// Also print each object's contents after its header
+    pub contents: bool,
+
+    /// Header format, `%(objectname) %(objecttype) %(objectsize)` by default
+    pub format: Option<String>,
+}
+
+pub enum Request {
+    Object(Info, String),
+    Batch(Batch),
+}
+
+impl From<Args> for Request {
     fn from(
-        InfoArgs {
-            r#type,
-            size,
-            print,
-        }: InfoArgs,
+        Args {
+            info:
+                InfoArgs {
+                    r#type,
+                    size,
+                    print,
+                    batch,
+                    batch_check,
+                },
+            hash,
+        }: Args,
     ) -> Self {
-        match (r#type, size, print) {
-            (true, _, _) => Self::Type,
-            (_, true, _) => Self::Size,
-            (_, _, true) => Self::Print,
+        let info = match (r#type, size, print, batch, batch_check) {
+            (true, _, _, _, _) => Info::Type,
+            (_, true, _, _, _) => Info::Size,
+            (_, _, true, _, _) => Info::Print,
+            (_, _, _, Some(format), _) => {
+                return Self::Batch(Batch {
+                    contents: true,
+                    format,
+                })
+            }
+            (_, _, _, _, Some(format)) => {
+                return Self::Batch(Batch {
+                    contents: false,
+                    format,
+                })
+            }
             _ => unreachable!("clap ensures at least one is present"),
-        }
+        };
+
+        Self::Object(info, hash.expect("clap requires a hash"))
     }
 }
 
 /// Prints an object's type, size, or contents if it exists in the .git database.
-pub fn cat_file(info: Info, hash: &str, mut output: impl Write) -> anyhow::Result<()> {
-    let path = utils::find_object(hash)?;
-
-    let mut decoder = ZlibDecoder::new(fs::File::open(path)?);
+pub fn cat_file(
+    store: &dyn ObjectStore,
+    info: Info,
+    hash: &str,
+    mut output: impl Write,
+) -> crate::Result<()> {
+    let oid = revision::resolve(store, hash)?;
 
     match info {
         Info::Type => {
-            let mut buf = [0u8; 64];
-            let mut count = decoder.read(&mut buf)?;
-            while count > 0 {
-                count = decoder.read(&mut buf[count..])?;
-            }
-            let (_, r#type) = parsing::parse_type(&buf)?;
-            write!(output, "{type}")?;
+            let header = store
+                .read_header(&oid)?
+                .ok_or_else(|| Error::ObjectNotFound(oid.to_string()))?;
+            write!(output, "{}", header.r#type)?;
         }
 
         Info::Size => {
-            let mut buf = [0u8; 64];
-            let mut count = decoder.read(&mut buf)?;
-            while count > 0 {
-                count = decoder.read(&mut buf[count..])?;
-            }
-            let (_, parsing::Header { size, .. }) = parsing::parse_header(&buf)?;
-            write!(output, "{size}")?;
+            let header = store
+                .read_header(&oid)?
+                .ok_or_else(|| Error::ObjectNotFound(oid.to_string()))?;
+            write!(output, "{}", header.size)?;
         }
 
         Info::Print => {
-            // possible optimization: read up to the filesize,
-            // then perform just one allocation for the next read
-            let mut buf = vec![];
-            decoder.read_to_end(&mut buf)?;
-            let (contents, r#type) = parsing::parse_contents(&buf)?;
-
-            // dispatch to ls_tree for tree objects
-            if matches!(r#type, parsing::Type::Tree) {
-                super::ls_tree::ls_tree(false, false, false, 20, hash, output)?;
-            } else {
-                output.write_all(contents)?;
+            let object = store.get(&oid)?;
+
+            match object.r#type {
+                // dispatch to ls_tree for tree objects
+                parsing::Type::Tree => super::ls_tree::ls_tree(
+                    store,
+                    false,
+                    false,
+                    false,
+                    super::ls_tree::Abbrev::Full,
+                    &oid.to_string(),
+                    output,
+                )?,
+
+                parsing::Type::Tag => {
+                    let (_, tag) = parsing::parse_tag(&object.contents)
+                        .map_err(|error| Error::corrupt(oid, error))?;
+                    write!(output, "{tag}")?;
+                }
+
+                _ => output.write_all(&object.contents)?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+enum Atom<'format> {
+    Literal(&'format str),
+    Name,
+    Type,
+    Size,
+    Rest,
+}
+
+fn parse_format(format: &str) -> crate::Result<Vec<Atom<'_>>> {
+    let mut atoms = vec![];
+    let mut format = format;
+
+    while let Some(start) = format.find("%(") {
+        if start > 0 {
+            atoms.push(Atom::Literal(&format[..start]));
+        }
+        let end = format[start..].find(')').ok_or_else(|| {
+            Error::Other(format!("unterminated format element {}", &format[start..]))
+        })?;
+        atoms.push(match &format[start + 2..start + end] {
+            "objectname" => Atom::Name,
+            "objecttype" => Atom::Type,
+            "objectsize" => Atom::Size,
+            "rest" => Atom::Rest,
+            element => crate::bail!("unknown format element: {element}"),
+        });
+        format = &format[start + end + 1..];
+    }
+    if !format.is_empty() {
+        atoms.push(Atom::Literal(format));
+    }
+
+    Ok(atoms)
+}
+
+/// Prints a header, and optionally the contents, for each object named on a line of `input`,
+/// in the same format as `git cat-file --batch`.
+pub fn cat_file_batch(
+    store: &dyn ObjectStore,
+    batch: Batch,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> crate::Result<()> {
+    let format = batch
+        .format
+        .as_deref()
+        .unwrap_or("%(objectname) %(objecttype) %(objectsize)");
+    let atoms = parse_format(format)?;
+    // the rest of each line is only split off from the name if it's printed
+    let split = atoms.iter().any(|atom| matches!(atom, Atom::Rest));
+
+    for line in input.lines() {
+        let line = line?;
+        let (name, rest) = if split {
+            let line = line.trim_start();
+            line.split_once(char::is_whitespace)
+                .map_or((line, ""), |(name, rest)| (name, rest.trim_start()))
+        } else {
+            (line.as_str(), "")
+        };
+
+        let header = match revision::resolve(store, name) {
+            Ok(oid) => store.read_header(&oid)?.map(|header| (oid, header)),
+            Err(Error::AmbiguousId { .. }) => {
+                writeln!(output, "{name} ambiguous")?;
+                output.flush()?;
+                continue;
+            }
+            // names that don't lead to an object of the right type are missing too
+            Err(Error::ObjectNotFound(_) | Error::InvalidRef { .. } | Error::WrongType { .. }) => {
+                None
             }
+            Err(error) => return Err(error),
+        };
+        let Some((oid, header)) = header else {
+            writeln!(output, "{name} missing")?;
+            output.flush()?;
+            continue;
+        };
+
+        for atom in &atoms {
+            match atom {
+                Atom::Literal(literal) => write!(output, "{literal}")?,
+                Atom::Name => write!(output, "{oid}")?,
+                Atom::Type => write!(output, "{}", header.r#type)?,
+                Atom::Size => write!(output, "{}", header.size)?,
+                Atom::Rest => write!(output, "{rest}")?,
+            }
+        }
+        writeln!(output)?;
+
+        if batch.contents {
+            output.write_all(&store.get(&oid)?.contents)?;
+            writeln!(output)?;
         }
+
+        // callers often wait for each answer before asking for the next object
+        output.flush()?;
     }
 
     Ok(())
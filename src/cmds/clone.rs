@@ -1,24 +1,18 @@
 use std::{
-    collections::HashMap,
-    env,
+    env, fs,
     io::{self, Write},
-    mem,
     path::{Path, PathBuf},
 };
 
-use anyhow::Context;
-use flate2::bufread::ZlibDecoder;
 use tokio::runtime::Runtime;
 
 use crate::{
     cmds,
     parsing::{self, pack_file_response},
-    SHA_LEN,
+    store::{Database, ObjectFormat, ObjectStore, PackStore},
+    utils, Error, ALTERNATES, DOT_GIT, INFO, OBJECTS, PACK,
 };
 
-const OBJ_TYPE_OFFSET_DELTA: u8 = 6;
-const OBJ_TYPE_REF_DELTA: u8 = 7;
-
 #[derive(clap::Args)]
 pub struct Args {
     /// Remote repository
@@ -26,11 +20,40 @@ pub struct Args {
 
     /// Repository path
     pub path: Option<PathBuf>,
+
+    /// Borrow the objects of a local remote repository, instead of copying them
+    #[arg(short, long)]
+    pub shared: bool,
+
+    /// Borrow objects from this local repository, and only fetch the ones it doesn't have
+    #[arg(long, value_name = "REPOSITORY")]
+    pub reference: Option<PathBuf>,
 }
 
-pub fn clone(remote: &str, path: impl AsRef<Path>, mut _output: impl Write) -> anyhow::Result<()> {
-    cmds::init::init(&path, io::sink())?;
-    env::set_current_dir(path)?;
+/// Clones a remote repository. The objects of other repositories can be borrowed
+/// through objects/info/alternates instead: all of them with `shared`, where the
+/// remote is a local path, or the ones the `reference` repository has.
+pub fn clone(
+    remote: &str,
+    path: impl AsRef<Path>,
+    shared: bool,
+    reference: Option<&Path>,
+    mut _output: impl Write,
+) -> crate::Result<()> {
+    // these are found before moving into the new repository, since they may be relative
+    let shared = shared.then(|| git_dir(Path::new(remote))).transpose()?;
+    let reference = reference.map(git_dir).transpose()?;
+    let alternates = shared
+        .iter()
+        .chain(&reference)
+        .map(|git_dir| git_dir.join(OBJECTS))
+        .collect::<Vec<_>>();
+
+    if let Some(shared) = shared {
+        cmds::init::init(&path, utils::object_format_in(&shared)?, io::sink())?;
+        env::set_current_dir(&path)?;
+        return write_alternates(&alternates);
+    }
 
     Runtime::new()?.block_on(async {
         let remote = remote.trim_end_matches('/');
@@ -41,11 +64,9 @@ pub fn clone(remote: &str, path: impl AsRef<Path>, mut _output: impl Write) -> a
             .get(format!("{remote}/info/refs?service={service}"))
             .send()
             .await?;
-        anyhow::ensure!(
-            [200, 304].contains(&response.status().as_u16()),
-            "received {}",
-            response.status()
-        );
+        if ![200, 304].contains(&response.status().as_u16()) {
+            return Err(Error::Protocol(format!("received {}", response.status())));
+        }
 
         let content_type = response
             .headers()
@@ -53,156 +74,125 @@ pub fn clone(remote: &str, path: impl AsRef<Path>, mut _output: impl Write) -> a
             .expect("always has content-type")
             .to_str()
             .expect("should be utf-8");
-        anyhow::ensure!(
-            content_type == "application/x-git-upload-pack-advertisement",
-            "received content-type: {content_type}"
-        );
+        if content_type != "application/x-git-upload-pack-advertisement" {
+            return Err(Error::Protocol(format!(
+                "received content-type: {content_type}"
+            )));
+        }
 
         let contents = response.bytes().await?;
-        let (_, refs) = parsing::advertisement_response(service)(&contents)
-            .context("invalid advertisement response body")?;
+        let (_, (refs, capabilities)) = parsing::advertisement_response(service)(&contents)
+            .map_err(|_| Error::Protocol("invalid advertisement response body".into()))?;
+
+        // the new repository uses the same hash function as the remote
+        let format = match capabilities
+            .iter()
+            .find_map(|capability| capability.strip_prefix("object-format="))
+        {
+            Some(format) => format.parse()?,
+            None => ObjectFormat::Sha1,
+        };
+        if let Some(reference) = &reference {
+            let reference_format = utils::object_format_in(reference)?;
+            crate::ensure!(
+                reference_format == format,
+                "the reference repository uses {reference_format}, but the remote uses {format}"
+            );
+        }
+        cmds::init::init(&path, format, io::sink())?;
+        env::set_current_dir(&path)?;
+        write_alternates(&alternates)?;
+
+        // only objects that can't be borrowed are fetched, and the
+        // ones that can tell the server what not to send
+        let store = Database::open(Path::new(DOT_GIT).join(OBJECTS))?;
+        let mut wants = vec![];
+        let mut haves = vec![];
+        for (oid, _) in refs {
+            if store.exists(&oid)? {
+                haves.push(oid);
+            } else {
+                wants.push(oid);
+            }
+        }
+        if wants.is_empty() {
+            return Ok(());
+        }
 
         let response = client
             .post(format!("{remote}/{service}"))
             .body({
-                use std::fmt::Write;
-
                 let mut body = String::new();
-                for (hash, _) in refs {
-                    writeln!(
-                        body,
-                        "0032want {}",
-                        std::str::from_utf8(&hash).expect("hex-encoded")
-                    )?;
+                for (i, oid) in wants.into_iter().enumerate() {
+                    // servers assume SHA-1 unless the first want says otherwise
+                    let line = if i == 0 && format != ObjectFormat::Sha1 {
+                        format!("want {oid} object-format={format}\n")
+                    } else {
+                        format!("want {oid}\n")
+                    };
+                    body.push_str(&format!("{:04x}{line}", line.len() + 4));
+                }
+                body.push_str("0000");
+                for oid in haves {
+                    let line = format!("have {oid}\n");
+                    body.push_str(&format!("{:04x}{line}", line.len() + 4));
                 }
-                writeln!(body, "00000009done")?;
+                body.push_str("0009done\n");
                 body
             })
             .send()
             .await?;
-        anyhow::ensure!(
-            response.status().is_success(),
-            "received {}",
-            response.status()
-        );
+        if !response.status().is_success() {
+            return Err(Error::Protocol(format!("received {}", response.status())));
+        }
 
         let contents = response.bytes().await?;
-        let (pack, _) = pack_file_response(&contents).context("invalid pack file response body")?;
-
-        let mut index = 12;
-        let mut decompressor = ZlibDecoder::new(Default::default());
-        let mut decompressed = vec![];
-        let mut objects = HashMap::new();
-        let mut delta_ref;
-        let mut delta_offset_index;
-
-        while pack[index..].len() > 20 {
-            let object_type = pack[index] << 1 >> 5;
-            let mut size = pack[index] as u64 & 0b0000_1111;
-            let mut shift = 4;
-            while pack[index] >= 128 {
-                index += 1;
-                size += (pack[index] as u64 & 0b0111_1111) << shift;
-                shift += 7;
-            }
-            index += 1;
-
-            (delta_ref, delta_offset_index) = (None, None);
-            if object_type == OBJ_TYPE_OFFSET_DELTA {
-                let mut offset = pack[index] as u64 & 0b0111_1111;
-                let mut shift = 7;
-                while pack[index] >= 128 {
-                    index += 1;
-                    offset += (pack[index] as u64 & 0b0111_1111) << shift;
-                    shift += 7;
-                }
-                index += 1;
-                delta_offset_index = Some(index - offset as usize);
-            } else if object_type == OBJ_TYPE_REF_DELTA {
-                delta_ref = Some(&pack[index..][..SHA_LEN]);
-                index += SHA_LEN;
-            }
-
-            decompressor.reset(&pack[index..]);
-            decompressed.clear();
-            if io::copy(&mut decompressor, &mut decompressed).is_err() {
-                break;
-            }
-            let out = decompressor.total_out();
-            anyhow::ensure!(size == out, "decompressed data does not match object size");
-            index += decompressor.total_in() as usize;
-
-            match (delta_ref, delta_offset_index) {
-                (None, None) => {
-                    let r#type = match object_type {
-                        1 => cmds::hash_object::Type::Commit,
-                        2 => cmds::hash_object::Type::Tree,
-                        3 => cmds::hash_object::Type::Blob,
-                        4 => cmds::hash_object::Type::Tag,
-                        _ => unreachable!("no other object types reachable"),
-                    };
+        let (pack, _) = pack_file_response(&contents)
+            .map_err(|_| Error::Protocol("invalid pack file response body".into()))?;
 
-                    let mut hash = [0u8; SHA_LEN];
-                    cmds::hash_object::hash_object(
-                        true,
-                        r#type,
-                        cmds::hash_object::Source::Buf(&decompressed),
-                        false,
-                        hash.as_mut(),
-                    )?;
-
-                    objects.insert(hash, (mem::take(&mut decompressed), r#type));
-                }
+        store_pack(pack)
+    })
+}
 
-                (Some(_delta_ref), _) => {
-                    continue;
-                    // let Some(&(ref old_object, r#type)) = objects.get(delta_ref) else {
-                    //     anyhow::bail!("failed to find reference in packfile")
-                    // };
-
-                    // let mut new_object = Vec::with_capacity(old_object.len());
-                    // let mut delta_iter = decompressed.iter();
-
-                    // // skip the size integers
-                    // delta_iter
-                    //     .by_ref()
-                    //     .take_while(|&&byte| byte >= 128)
-                    //     .for_each(|_| ());
-                    // delta_iter
-                    //     .by_ref()
-                    //     .take_while(|&&byte| byte >= 128)
-                    //     .for_each(|_| ());
-
-                    // while let Some(&byte) = delta_iter.next() {
-                    //     if byte < 128 {
-                    //         // INSERT
-                    //         let inserting = byte as usize & 0b0111_1111;
-                    //         new_object.extend(delta_iter.by_ref().take(inserting));
-                    //     } else {
-                    //         // COPY
-                    //         let _bytes_to_read = byte as usize & 0b0000_1111;
-                    //     }
-                    // }
-
-                    // let mut hash = [0u8; SHA_LEN];
-                    // cmds::hash_object::hash_object(
-                    //     true,
-                    //     r#type,
-                    //     cmds::hash_object::Source::Buf(&new_object),
-                    //     false,
-                    //     hash.as_mut(),
-                    // )?;
-
-                    // objects.insert(hash, (new_object, r#type));
-                }
+/// The git directory of a local repository, either its .git directory or
+/// the repository itself if it's bare
+fn git_dir(repository: &Path) -> crate::Result<PathBuf> {
+    let git_dir = if repository.join(DOT_GIT).is_dir() {
+        repository.join(DOT_GIT)
+    } else {
+        repository.to_owned()
+    };
+    crate::ensure!(
+        git_dir.join(OBJECTS).is_dir(),
+        "'{}' does not appear to be a git repository",
+        repository.display()
+    );
+    Ok(git_dir.canonicalize()?)
+}
 
-                (_, Some(_delta_offset_index)) => {
-                    continue;
-                    // writeln!(output, "OFFSET INDEX {delta_offset_index}")?;
-                }
-            }
-        }
+/// Lists object directories in objects/info/alternates, for the database to borrow from
+fn write_alternates(alternates: &[PathBuf]) -> crate::Result<()> {
+    if alternates.is_empty() {
+        return Ok(());
+    }
+
+    let info = Path::new(DOT_GIT).join(OBJECTS).join(INFO);
+    fs::create_dir_all(&info)?;
+    let mut contents = String::new();
+    for alternate in alternates {
+        contents.push_str(&alternate.to_string_lossy());
+        contents.push('\n');
+    }
+    fs::write(info.join(ALTERNATES), contents)?;
+
+    Ok(())
+}
 
-        Ok(())
-    })
+/// Stores a packfile and its index in the .git database.
+pub fn store_pack(pack: &[u8]) -> crate::Result<()> {
+    let format = utils::object_format()?;
+    let entries = cmds::index_pack::index_entries(pack, format)?;
+    PackStore::open(Path::new(DOT_GIT).join(OBJECTS).join(PACK), format)?
+        .add_pack(pack, entries)?;
+    Ok(())
 }
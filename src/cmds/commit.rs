@@ -1,6 +1,6 @@
 use std::io::Write;
 
-use crate::{cmds, utils};
+use crate::{cmds, store::ObjectStore, utils, Error};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -9,11 +9,19 @@ pub struct Args {
     pub message: String,
 }
 
-pub fn commit(message: String, mut output: impl Write) -> anyhow::Result<()> {
+pub fn commit(
+    store: &dyn ObjectStore,
+    message: String,
+    mut output: impl Write,
+) -> crate::Result<()> {
     let parent = utils::get_head()?;
     let mut commit_hash = vec![];
-    cmds::commit_tree::commit_tree(parent.as_slice(), &message, None, &mut commit_hash)?;
-    utils::update_head(std::str::from_utf8(&commit_hash)?.trim())?;
+    cmds::commit_tree::commit_tree(store, parent.as_slice(), &message, None, &mut commit_hash)?;
+    utils::update_head(
+        std::str::from_utf8(&commit_hash)
+            .map_err(|error| Error::Other(error.to_string()))?
+            .trim(),
+    )?;
 
     Ok(write!(output, "New commit saved with message:\n{message}")?)
 }
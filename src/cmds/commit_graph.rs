@@ -0,0 +1,26 @@
+use std::{fs, path::Path};
+
+use crate::{commit_graph, store::ObjectStore, utils, COMMIT_GRAPH, INFO};
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(subcommand)]
+    pub action: Action,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Action {
+    /// Write a commit-graph file of the commits reachable from the refs, HEAD and reflogs
+    Write,
+}
+
+/// Writes objects/info/commit-graph, replacing any previous one
+pub fn write(store: &dyn ObjectStore, objects: &Path) -> crate::Result<()> {
+    let info = objects.join(INFO);
+    fs::create_dir_all(&info)?;
+
+    utils::write_atomic(&info.join(COMMIT_GRAPH), |file| {
+        commit_graph::write_commit_graph(store, &utils::root_objects()?, file)
+    })?;
+    Ok(())
+}
@@ -1,10 +1,6 @@
-use std::io::{self, Read, Write};
+use std::io::Write;
 
-use anyhow::Context;
-use flate2::read::ZlibEncoder;
-use sha1::{Digest, Sha1};
-
-use crate::{utils, SHA_DISPLAY_LEN};
+use crate::{parsing::Type, revision, store::ObjectStore, utils};
 
 use super::write_tree::write_tree;
 
@@ -23,33 +19,27 @@ pub struct Args {
 }
 
 pub fn commit_tree(
+    store: &dyn ObjectStore,
     parents: &[String],
     message: &str,
     tree_hash: Option<&str>,
     mut output: impl Write,
-) -> anyhow::Result<()> {
+) -> crate::Result<()> {
     let name = utils::get_config_value("user", "name")?.unwrap_or_else(|| "Anonymous".into());
     let email = utils::get_config_value("user", "email")?.unwrap_or_else(|| "N/A".into());
 
-    // hacky way to get the full hash if the hash is abbreviated
-    let get_full_hash = |hash: &str| -> anyhow::Result<_> {
-        let hash = utils::find_object(hash.trim()).context("failed to find parent")?;
-        let hash = hash.to_str().expect("path is utf-8").replace('/', "");
-        Ok(hash[hash.len() - SHA_DISPLAY_LEN..].to_owned())
-    };
-
     let mut contents = vec![];
     write!(contents, "tree ")?;
 
     if let Some(tree_hash) = tree_hash {
-        let tree_hash = get_full_hash(tree_hash)?;
+        let tree_hash = revision::resolve_as(store, tree_hash.trim(), Type::Tree)?;
         writeln!(&mut contents, "{tree_hash}")?;
     } else {
-        write_tree(&mut contents)?;
+        write_tree(store, &mut contents)?;
     }
 
     for parent in parents {
-        let parent = get_full_hash(parent)?;
+        let parent = revision::resolve_as(store, parent.trim(), Type::Commit)?;
         writeln!(&mut contents, "parent {parent}")?;
     }
 
@@ -64,26 +54,9 @@ pub fn commit_tree(
         chrono::Local::now().format("%s %z")
     )?;
 
-    let header = format!("commit {}\0", contents.len());
-
-    let mut hasher = Sha1::new();
-    io::copy(
-        &mut header.as_bytes().chain(contents.as_slice()),
-        &mut hasher,
-    )?;
-    let hash = hasher.finalize();
-
-    let mut file = utils::create_object(&hash.into())?;
-    let mut compressor = ZlibEncoder::new(
-        header.as_bytes().chain(contents.as_slice()),
-        Default::default(), // default compression is level 6
-    );
-    io::copy(&mut compressor, &mut file)?;
+    let oid = store.write(Type::Commit, &contents)?;
 
-    for byte in hash {
-        write!(output, "{byte:02x}")?;
-    }
-    writeln!(output)?;
+    writeln!(output, "{oid}")?;
 
     Ok(())
 }
@@ -1,8 +1,9 @@
 use std::io::Write;
 
-use anyhow::Context;
-
-use crate::utils::{get_config_value, list_config, set_config_value};
+use crate::{
+    utils::{get_config_value, list_config, set_config_value},
+    Error,
+};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -46,16 +47,21 @@ impl From<Args> for Action {
     }
 }
 
-pub fn config(action: Action, mut output: impl Write) -> anyhow::Result<()> {
+pub fn config(action: Action, mut output: impl Write) -> crate::Result<()> {
     match action {
         Action::Get(key) => {
-            let (section, key) = key.split_once('.').context("key must contain a section")?;
-            let value = get_config_value(section, key)?.context("no value found")?;
+            let (section, key) = key
+                .split_once('.')
+                .ok_or_else(|| Error::Config("key must contain a section".into()))?;
+            let value = get_config_value(section, key)?
+                .ok_or_else(|| Error::Config("no value found".into()))?;
             output.write_all(value.as_bytes())?;
         }
 
         Action::Set(key, value) => {
-            let (section, key) = key.split_once('.').context("key must contain a section")?;
+            let (section, key) = key
+                .split_once('.')
+                .ok_or_else(|| Error::Config("key must contain a section".into()))?;
             set_config_value(section, key, value)?;
         }
 
@@ -0,0 +1,138 @@
+use std::{fs, io::Write, path::Path};
+
+use crate::{
+    pack::Index,
+    store::{Database, ObjectStore},
+    PACK,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Also report packs, packed objects and garbage
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+#[derive(Default)]
+struct Counts {
+    count: u64,
+    size: u64,
+    in_pack: u64,
+    packs: u64,
+    size_pack: u64,
+    prune_packable: u64,
+    garbage: u64,
+    size_garbage: u64,
+}
+
+/// Reports how many loose objects are in the `objects` directory and the disk
+/// space they use, and with `verbose`, the same for packs and stray files.
+pub fn count_objects(
+    store: &Database,
+    objects: &Path,
+    verbose: bool,
+    mut output: impl Write,
+) -> crate::Result<()> {
+    let mut counts = Counts::default();
+    let hex_len = store.format().hex_len();
+
+    for entry in fs::read_dir(objects)? {
+        let entry = entry?;
+        let sha_dir = entry.file_name().to_string_lossy().into_owned();
+        if sha_dir.len() != 2 || !is_hex(&sha_dir) || !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        for entry in fs::read_dir(entry.path())? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let sha_file = entry.file_name().to_string_lossy().into_owned();
+
+            match format!("{sha_dir}{sha_file}").parse() {
+                Ok(oid) if sha_file.len() == hex_len - 2 && is_hex(&sha_file) => {
+                    counts.count += 1;
+                    counts.size += disk_usage(&metadata);
+                    if store.packs().exists(&oid)? {
+                        counts.prune_packable += 1;
+                    }
+                }
+                _ => {
+                    counts.garbage += 1;
+                    counts.size_garbage += disk_usage(&metadata);
+                }
+            }
+        }
+    }
+
+    if let Ok(entries) = fs::read_dir(objects.join(PACK)) {
+        for entry in entries {
+            let path = entry?.path();
+            let metadata = fs::metadata(&path)?;
+            let extension = path.extension().and_then(|extension| extension.to_str());
+
+            // a pack is only usable with its index, and the other way around
+            let paired = match extension {
+                Some("pack") => path.with_extension("idx").is_file(),
+                Some("idx") => path.with_extension("pack").is_file(),
+                // extra files git keeps next to packs
+                Some("keep" | "bitmap" | "rev" | "promisor" | "mtimes") => true,
+                _ => false,
+            };
+            if !paired {
+                counts.garbage += 1;
+                counts.size_garbage += disk_usage(&metadata);
+                continue;
+            }
+
+            match extension {
+                Some("pack") => {
+                    counts.packs += 1;
+                    counts.size_pack += metadata.len();
+                }
+                Some("idx") => {
+                    counts.in_pack += Index::open(&path, store.format())?.len() as u64;
+                    counts.size_pack += metadata.len();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if verbose {
+        writeln!(output, "count: {}", counts.count)?;
+        writeln!(output, "size: {}", counts.size / 1024)?;
+        writeln!(output, "in-pack: {}", counts.in_pack)?;
+        writeln!(output, "packs: {}", counts.packs)?;
+        writeln!(output, "size-pack: {}", counts.size_pack / 1024)?;
+        writeln!(output, "prune-packable: {}", counts.prune_packable)?;
+        writeln!(output, "garbage: {}", counts.garbage)?;
+        writeln!(output, "size-garbage: {}", counts.size_garbage / 1024)?;
+    } else {
+        writeln!(
+            output,
+            "{} objects, {} kilobytes",
+            counts.count,
+            counts.size / 1024
+        )?;
+    }
+
+    Ok(())
+}
+
+fn is_hex(name: &str) -> bool {
+    name.bytes()
+        .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
+
+/// Space a file takes up on disk, which is what git reports rather than its length
+fn disk_usage(metadata: &fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.blocks() * 512
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.len()
+    }
+}
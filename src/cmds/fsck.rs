@@ -0,0 +1,293 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    io::Write,
+};
+
+use crate::{
+    parsing::{self, Type},
+    store::{self, ObjectStore, Oid},
+    utils::{self, GITLINK},
+    Error,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Show every object that isn't reachable from a ref, not just dangling ones
+    #[arg(long)]
+    pub unreachable: bool,
+
+    /// Don't show objects that nothing refers to
+    #[arg(long)]
+    pub no_dangling: bool,
+}
+
+// the bits of the exit status, which tell apart the kinds of problems like git's
+const ERROR_OBJECT: u8 = 0b01;
+const ERROR_REACHABLE: u8 = 0b10;
+
+/// An object that was read successfully, and the objects it refers to
+struct Checked {
+    r#type: Type,
+    links: Vec<(Type, Oid)>,
+}
+
+/// Checks every object in `store`, then walks from the refs and reflogs to find
+/// missing and unreachable objects. Missing and dangling objects are reported to
+/// `output`, and problems with individual objects to `errors`, like git does.
+pub fn fsck(
+    store: &dyn ObjectStore,
+    unreachable: bool,
+    dangling: bool,
+    mut output: impl Write,
+    mut errors: impl Write,
+) -> crate::Result<()> {
+    let mut status = 0;
+    let mut checked = BTreeMap::new();
+    let mut corrupt = BTreeSet::new();
+
+    for oid in store.iter()? {
+        match check_object(store, &oid, &mut errors)? {
+            Some((object, ok)) => {
+                if !ok {
+                    status |= ERROR_OBJECT;
+                }
+                checked.insert(oid, object);
+            }
+            None => {
+                status |= ERROR_OBJECT;
+                corrupt.insert(oid);
+            }
+        }
+    }
+
+    let mut roots = utils::list_refs()?;
+    if let Some(head) = utils::resolve_head()? {
+        roots.push(("HEAD".into(), head));
+    }
+
+    let mut reachable = BTreeSet::new();
+    let mut missing = BTreeMap::new();
+    let mut stack = vec![];
+    for (name, oid) in roots {
+        if checked.contains_key(&oid) {
+            stack.push(oid);
+        } else {
+            writeln!(errors, "error: {name}: invalid sha1 pointer {oid}")?;
+            status |= ERROR_REACHABLE;
+        }
+    }
+    if stack.is_empty() {
+        writeln!(errors, "notice: No default references")?;
+    }
+    // old commits in the reflogs are kept alive too
+    for (name, oid) in utils::reflog_entries()? {
+        if checked.contains_key(&oid) {
+            stack.push(oid);
+        } else {
+            writeln!(errors, "error: {name}: invalid reflog entry {oid}")?;
+            status |= ERROR_REACHABLE;
+        }
+    }
+
+    while let Some(oid) = stack.pop() {
+        if !reachable.insert(oid) {
+            continue;
+        }
+        let Some(object) = checked.get(&oid) else {
+            continue;
+        };
+
+        for &(r#type, link) in &object.links {
+            match checked.get(&link) {
+                Some(linked) if linked.r#type != r#type => {
+                    writeln!(
+                        errors,
+                        "error: object {link} is a {}, not a {type}",
+                        linked.r#type
+                    )?;
+                    status |= ERROR_REACHABLE;
+                }
+                Some(_) => stack.push(link),
+                None if corrupt.contains(&link) => {}
+                None => {
+                    missing.insert(link, r#type);
+                }
+            }
+        }
+    }
+
+    for (oid, r#type) in &missing {
+        writeln!(output, "missing {type} {oid}")?;
+        status |= ERROR_REACHABLE;
+    }
+
+    // dangling objects are the tips of unreachable history:
+    // nothing at all refers to them, not even other unreachable objects
+    let referenced = checked
+        .values()
+        .flat_map(|object| object.links.iter().map(|(_, link)| *link))
+        .collect::<BTreeSet<_>>();
+    for (oid, object) in &checked {
+        if reachable.contains(oid) {
+            continue;
+        }
+        if unreachable {
+            writeln!(output, "unreachable {} {oid}", object.r#type)?;
+        } else if dangling && !referenced.contains(oid) {
+            writeln!(output, "dangling {} {oid}", object.r#type)?;
+        }
+    }
+
+    if status != 0 {
+        return Err(Error::ChecksFailed {
+            status,
+            reason: "the repository has errors".into(),
+        });
+    }
+    Ok(())
+}
+
+/// Reads an object and checks that it's well-formed. Returns `None` if it
+/// can't be read at all, otherwise whether it passed every check.
+fn check_object(
+    store: &dyn ObjectStore,
+    oid: &Oid,
+    mut errors: impl Write,
+) -> crate::Result<Option<(Checked, bool)>> {
+    let (object, header) = match (store.read(oid), store.read_header(oid)) {
+        (Ok(Some(object)), Ok(Some(header))) => (object, header),
+        _ => {
+            writeln!(errors, "error: {oid}: object corrupt or missing")?;
+            return Ok(None);
+        }
+    };
+
+    if store::hash(store.format(), object.r#type, &object.contents) != *oid {
+        writeln!(errors, "error: hash mismatch {oid}")?;
+        return Ok(None);
+    }
+    if header.r#type != object.r#type || header.size != object.contents.len() {
+        writeln!(
+            errors,
+            "error: {oid}: object header doesn't match its contents"
+        )?;
+        return Ok(None);
+    }
+
+    let mut problems = BTreeSet::new();
+    let mut links = vec![];
+    match object.r#type {
+        Type::Blob => {}
+        Type::Tree => match parsing::parse_tree(store.format())(&object.contents) {
+            Ok(([], entries)) => {
+                problems = check_tree(&entries);
+                links = entries
+                    .iter()
+                    .filter(|entry| entry.mode != GITLINK)
+                    .map(|entry| {
+                        let r#type = if entry.tree { Type::Tree } else { Type::Blob };
+                        (r#type, entry.hash)
+                    })
+                    .collect();
+            }
+            _ => {
+                problems.insert(Problem::Error("badTree: could not be parsed"));
+            }
+        },
+        Type::Commit => match parsing::parse_commit(&object.contents) {
+            Ok((_, commit)) => {
+                links.push((Type::Tree, commit.tree));
+                links.extend(commit.parents.iter().map(|parent| (Type::Commit, *parent)));
+            }
+            Err(_) => {
+                problems.insert(Problem::Error("badCommit: could not be parsed"));
+            }
+        },
+        Type::Tag => match parsing::parse_tag(&object.contents) {
+            Ok((_, tag)) => {
+                if tag.tagger.is_none() {
+                    problems.insert(Problem::Warning(
+                        "missingTaggerEntry: invalid format - expected 'tagger' line",
+                    ));
+                }
+                links.push((tag.r#type, tag.object));
+            }
+            Err(_) => {
+                problems.insert(Problem::Error("badTag: could not be parsed"));
+            }
+        },
+    }
+
+    let mut ok = true;
+    for problem in problems {
+        let (level, message) = match problem {
+            Problem::Error(message) => {
+                ok = false;
+                ("error", message)
+            }
+            Problem::Warning(message) => ("warning", message),
+        };
+        writeln!(errors, "{level} in {} {oid}: {message}", object.r#type)?;
+    }
+
+    Ok(Some((
+        Checked {
+            r#type: object.r#type,
+            links,
+        },
+        ok,
+    )))
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Problem {
+    Error(&'static str),
+    Warning(&'static str),
+}
+
+/// The problems with a tree's entries, each reported once
+fn check_tree(entries: &[utils::Entry]) -> BTreeSet<Problem> {
+    let mut problems = BTreeSet::new();
+
+    for entry in entries {
+        if !matches!(entry.mode, 100_644 | 100_755 | 120_000 | 40_000 | GITLINK) {
+            problems.insert(Problem::Warning("badFilemode: contains bad file modes"));
+        }
+        if entry.hash.as_bytes().iter().all(|&byte| byte == 0) {
+            problems.insert(Problem::Warning(
+                "nullSha1: contains entries pointing to null sha1",
+            ));
+        }
+        match entry.name.as_str() {
+            "" => problems.insert(Problem::Warning("emptyName: contains empty pathname")),
+            "." => problems.insert(Problem::Warning("hasDot: contains '.'")),
+            ".." => problems.insert(Problem::Warning("hasDotdot: contains '..'")),
+            name if name.eq_ignore_ascii_case(".git") => {
+                problems.insert(Problem::Warning("hasDotgit: contains '.git'"))
+            }
+            name if name.contains('/') => {
+                problems.insert(Problem::Warning("fullPathname: contains full pathnames"))
+            }
+            _ => false,
+        };
+    }
+
+    // a file and a tree with the same name can be apart in tree order, like
+    // "a", "a.txt" then the tree "a", so every name seen is remembered
+    let mut names = HashSet::new();
+    if !entries
+        .iter()
+        .all(|entry| names.insert(entry.name.as_str()))
+    {
+        problems.insert(Problem::Error(
+            "duplicateEntries: contains duplicate file entries",
+        ));
+    }
+    for pair in entries.windows(2) {
+        if pair[0].name != pair[1].name && pair[0].cmp_tree_order(&pair[1]).is_ge() {
+            problems.insert(Problem::Error("treeNotSorted: not properly sorted"));
+        }
+    }
+
+    problems
+}
@@ -0,0 +1,106 @@
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{
+    pack::{DeltaLimits, Index},
+    store::{Database, ObjectFormat, ObjectStore, Oid},
+    utils,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Number of objects to try as the delta base of each object
+    #[arg(long, default_value_t = DeltaLimits::default().window)]
+    pub window: usize,
+
+    /// Longest chain of deltas needed to rebuild an object
+    #[arg(long, default_value_t = DeltaLimits::default().depth)]
+    pub depth: usize,
+}
+
+/// Packs every object reachable from the refs into a single pack, with bitmaps
+/// if repack.writeBitmaps is true, then deletes the loose objects and packs
+/// it replaces. Unreachable objects from old packs are kept as loose objects, so
+/// that pruning decides when they're deleted.
+pub fn gc(store: &Database, limits: DeltaLimits) -> crate::Result<()> {
+    let roots = utils::root_objects()?;
+    let reachable = utils::reachable_objects(store, &roots, &BTreeSet::new())?;
+    let old_packs = store.packs().paths();
+
+    let mut objects = Vec::with_capacity(reachable.len());
+    for (oid, name) in &reachable {
+        // objects borrowed from alternates stay there, like `git repack -l`
+        if store.has_local(oid)? {
+            objects.push((*oid, name.clone()));
+        }
+    }
+    let new_pack = if objects.is_empty() {
+        None
+    } else {
+        let path = store.packs().write_objects(store, &objects, limits)?;
+        check_pack(&path, store.format(), &objects)?;
+        Some(path)
+    };
+    // bitmaps can only describe a pack that has everything the refs reach
+    if let Some(new_pack) = &new_pack {
+        if objects.len() == reachable.len() && utils::write_bitmaps()? {
+            store.packs().write_bitmap(new_pack, store, &roots)?;
+        }
+    }
+    let reachable = reachable
+        .into_iter()
+        .map(|(oid, _)| oid)
+        .collect::<BTreeSet<_>>();
+
+    for oid in store.packs().iter()? {
+        if !reachable.contains(&oid) {
+            let object = store.get(&oid)?;
+            store.loose().write(object.r#type, &object.contents)?;
+        }
+    }
+    for path in old_packs {
+        if new_pack.as_ref() != Some(&path) {
+            store.packs().remove_pack(&path)?;
+        }
+    }
+
+    for oid in store.loose().iter()? {
+        if reachable.contains(&oid) {
+            store.loose().remove(&oid)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads back a pack that was just written and checks that it has every
+/// object it should, before anything it replaces is deleted
+fn check_pack(path: &Path, format: ObjectFormat, objects: &[(Oid, String)]) -> crate::Result<()> {
+    let index = Index::open(path.with_extension("idx"), format)?;
+
+    let mut pack = File::open(path)?;
+    let mut checksum = vec![0; format.hash_len()];
+    pack.seek(SeekFrom::End(-(checksum.len() as i64)))?;
+    pack.read_exact(&mut checksum)?;
+    crate::ensure!(
+        index.pack_checksum() == checksum,
+        CorruptPack,
+        "{} does not match its index",
+        path.display()
+    );
+
+    for (oid, _) in objects {
+        crate::ensure!(
+            index.position(oid).is_some(),
+            CorruptPack,
+            "{} is missing {oid}",
+            path.display()
+        );
+    }
+
+    Ok(())
+}
@@ -1,14 +1,16 @@
 use std::{
     borrow::Cow,
-    fmt, fs,
+    fmt,
+    fs::{self, File},
     io::{self, Read, Write},
     path::PathBuf,
 };
 
-use flate2::read::ZlibEncoder;
-use sha1::{Digest, Sha1};
-
-use crate::utils;
+use crate::{
+    parsing,
+    store::{self, ObjectStore, Oid},
+    utils,
+};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -43,6 +45,17 @@ impl fmt::Display for Type {
     }
 }
 
+impl From<Type> for parsing::Type {
+    fn from(r#type: Type) -> Self {
+        match r#type {
+            Type::Blob => Self::Blob,
+            Type::Commit => Self::Commit,
+            Type::Tree => Self::Tree,
+            Type::Tag => Self::Tag,
+        }
+    }
+}
+
 #[derive(clap::Args)]
 #[group(required = true, multiple = false)]
 pub struct SourceArgs {
@@ -70,15 +83,30 @@ impl From<SourceArgs> for Source<'_> {
     }
 }
 
-/// Prints the sha1 hash of a file, and writes it to the .git
+/// Prints the hash of a file, and writes it to the .git
 /// database as a blob if `write == true`.
 pub fn hash_object(
+    store: &dyn ObjectStore,
     write: bool,
     r#type: Type,
     source: Source,
     as_hex: bool,
-    mut output: impl Write,
-) -> anyhow::Result<()> {
+    output: impl Write,
+) -> crate::Result<()> {
+    // large files are hashed and compressed as they're read instead of being buffered
+    if let Source::Path(path) = &source {
+        let size = fs::metadata(path)?.len();
+        if size > utils::big_file_threshold()? {
+            let mut file = File::open(path)?;
+            let oid = if write {
+                store.write_stream(r#type.into(), size, &mut file)?
+            } else {
+                store::hash_stream(store.format(), r#type.into(), size, &mut file)?
+            };
+            return print_oid(oid, as_hex, output);
+        }
+    }
+
     let contents = match source {
         Source::Path(path) => Cow::Owned(fs::read(path)?),
         Source::Stdin => {
@@ -89,34 +117,20 @@ pub fn hash_object(
         Source::Buf(buf) => Cow::Borrowed(buf),
     };
 
-    let header = format!("{type} {}\0", contents.len());
-
-    let mut hasher = Sha1::new();
+    let oid = if write {
+        store.write(r#type.into(), &contents)?
+    } else {
+        store::hash(store.format(), r#type.into(), &contents)
+    };
 
-    // could optimize this function by writing directly to a file and
-    // the hasher at the same time, then moving the file to the final location.
-    // would prevent needing the 'contents' in-memory buffer.
-    io::copy(&mut header.as_bytes().chain(contents.as_ref()), &mut hasher)?;
+    print_oid(oid, as_hex, output)
+}
 
-    let hash = hasher.finalize();
+fn print_oid(oid: Oid, as_hex: bool, mut output: impl Write) -> crate::Result<()> {
     if as_hex {
-        for byte in &hash {
-            write!(output, "{byte:02x}")?;
-        }
-        writeln!(output)?;
+        writeln!(output, "{oid}")?;
     } else {
-        output.write_all(&hash)?;
-    }
-
-    if write {
-        let mut file = utils::create_object(&hash.into())?;
-
-        let mut compressor = ZlibEncoder::new(
-            header.as_bytes().chain(contents.as_ref()),
-            Default::default(), // default compression is level 6
-        );
-
-        io::copy(&mut compressor, &mut file)?;
+        output.write_all(oid.as_bytes())?;
     }
 
     Ok(())
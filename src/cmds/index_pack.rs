@@ -0,0 +1,75 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    pack::{self, IndexEntry, PackReader},
+    store::{ObjectFormat, Oid},
+    utils,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Write the index to this file, instead of next to the pack
+    #[arg(short)]
+    pub output: Option<PathBuf>,
+
+    /// The packfile to index
+    pub pack: PathBuf,
+}
+
+/// Checks every object in a packfile and writes an index for it,
+/// then prints the pack's checksum.
+pub fn index_pack(
+    pack_path: &Path,
+    index_path: Option<&Path>,
+    format: ObjectFormat,
+    mut output: impl Write,
+) -> crate::Result<()> {
+    let index_path = match index_path {
+        Some(index_path) => index_path.to_path_buf(),
+        None => {
+            crate::ensure!(
+                pack_path
+                    .extension()
+                    .is_some_and(|extension| extension == "pack"),
+                "packfile name '{}' does not end with '.pack'",
+                pack_path.display()
+            );
+            pack_path.with_extension("idx")
+        }
+    };
+
+    let pack = fs::read(pack_path)?;
+    let entries = index_entries(&pack, format)?;
+    let checksum = Oid::from_bytes(&pack[pack.len() - format.hash_len()..])?;
+
+    utils::write_atomic(&index_path, |file| {
+        pack::write_index(entries, &checksum, file)
+    })?;
+
+    writeln!(output, "{checksum}")?;
+
+    Ok(())
+}
+
+/// Resolves every object in a packfile, including deltas, and returns its index entries.
+/// Object ids and the pack's checksum use the given format.
+pub fn index_entries(pack: &[u8], format: ObjectFormat) -> crate::Result<Vec<IndexEntry>> {
+    let reader = PackReader::new(pack, format)?;
+    let mut entries = Vec::with_capacity(reader.len());
+
+    // an index can only describe objects that are in its pack
+    pack::resolve_pack(reader, &|_| Ok(None), |object| {
+        entries.push(IndexEntry {
+            oid: object.oid,
+            crc32: object.entry.crc32,
+            offset: object.entry.offset,
+        });
+        Ok(())
+    })?;
+
+    Ok(entries)
+}
@@ -1,28 +1,42 @@
-use anyhow::Context;
 use std::{
     fs,
     io::Write,
     path::{Path, PathBuf},
 };
 
-use crate::{CONFIG, DOT_GIT, HEAD, HEADS, OBJECTS, REFS, TAGS};
+use crate::{store::ObjectFormat, Error, CONFIG, DOT_GIT, HEAD, HEADS, OBJECTS, REFS, TAGS};
 
 #[derive(clap::Args)]
 pub struct Args {
+    /// Hash function for naming objects, either sha1 or sha256
+    #[arg(long, value_name = "FORMAT", default_value_t = ObjectFormat::Sha1)]
+    pub object_format: ObjectFormat,
+
     /// Path to use for initializing the repository
     pub path: Option<PathBuf>,
 }
 
 /// Initializes a new git repository by creating the .git directory and its subdirectories.
-pub fn init(path: impl AsRef<Path>, mut output: impl Write) -> anyhow::Result<()> {
+pub fn init(
+    path: impl AsRef<Path>,
+    format: ObjectFormat,
+    mut output: impl Write,
+) -> crate::Result<()> {
     let path = path.as_ref().join(DOT_GIT);
-    let config = "
+    let mut config = format!(
+        "
 [core]
-\trepositoryformatversion = 0
+\trepositoryformatversion = {}
 \tfilemode = true
 \tbare = false
 \tlogallrefupdates = true
-";
+",
+        // extensions are only understood by version 1 repositories
+        if format == ObjectFormat::Sha1 { 0 } else { 1 }
+    );
+    if format != ObjectFormat::Sha1 {
+        config.push_str(&format!("[extensions]\n\tobjectformat = {format}\n"));
+    }
 
     fs::create_dir_all(&path)
         .and_then(|_| fs::create_dir(path.join(OBJECTS)))
@@ -30,8 +44,10 @@ pub fn init(path: impl AsRef<Path>, mut output: impl Write) -> anyhow::Result<()
         .and_then(|_| fs::create_dir(path.join(REFS).join(HEADS)))
         .and_then(|_| fs::create_dir(path.join(REFS).join(TAGS)))
         .and_then(|_| fs::write(path.join(HEAD), "ref: refs/heads/main\n"))
-        .and_then(|_| fs::write(path.join(CONFIG), config))
-        .with_context(|| format!("failed to initialize {}", path.display()))?;
+        .and_then(|_| fs::write(path.join(CONFIG), &config))
+        .map_err(|error| {
+            Error::Other(format!("failed to initialize {}: {error}", path.display()))
+        })?;
 
     writeln!(output, "Initialized git directory")?;
 
@@ -1,12 +1,13 @@
-use std::{cmp, io::Write};
+use std::{cmp, collections::HashSet, io::Write};
 
-use anyhow::Context;
 use chrono::{Local, TimeZone};
 
 use crate::{
-    cmds,
-    parsing::{self, Commit},
-    utils, SHA_DISPLAY_LEN,
+    cache::ObjectCache,
+    parsing::{Commit, Type},
+    revision,
+    store::ObjectStore,
+    utils, Error, HEAD,
 };
 
 #[derive(clap::Args)]
@@ -19,42 +20,48 @@ pub struct Args {
     pub hash: Option<String>,
 }
 
-pub fn log(oneline: bool, hash: Option<&str>, mut output: impl Write) -> anyhow::Result<()> {
-    let hash = if let Some(hash) = hash {
-        let hash = utils::find_object(hash.trim()).context("failed to find parent")?;
-        let hash = hash.to_str().expect("path is utf-8").replace('/', "");
-        hash[hash.len() - SHA_DISPLAY_LEN..].to_owned()
+pub fn log(
+    store: &dyn ObjectStore,
+    oneline: bool,
+    hash: Option<&str>,
+    mut output: impl Write,
+) -> crate::Result<()> {
+    let oid = if let Some(hash) = hash {
+        revision::resolve_as(store, hash.trim(), Type::Commit)?
     } else {
-        utils::get_head()?.context("no commits to display")?
+        utils::resolve_head()?.ok_or_else(|| Error::invalid_ref(HEAD, "no commits to display"))?
     };
 
-    fn get_commits(hash: &str, commits: &mut Vec<Commit>) -> anyhow::Result<()> {
-        let mut contents = vec![];
-        cmds::cat_file::cat_file(cmds::cat_file::Info::Print, hash, &mut contents)?;
-        let (_, mut commit) = parsing::parse_commit(&contents)?;
-        commit.hash = Some(hash.into());
-        for parent in &commit.parents {
-            get_commits(std::str::from_utf8(parent)?, commits)?;
+    // the walk only needs parents and dates, which the commit-graph has
+    // without parsing each commit, so commits are only read once they're printed
+    let cache = ObjectCache::open(store)?;
+    let mut seen = HashSet::new();
+    let mut history = vec![];
+    let mut stack = vec![oid];
+    while let Some(oid) = stack.pop() {
+        if !seen.insert(oid) {
+            continue;
         }
-        commits.push(commit);
-
-        Ok(())
+        let node = cache.commit_node(&oid)?;
+        stack.extend(node.parents.iter().rev());
+        history.push((node.commit_time, oid));
     }
+    // newest first, like git
+    history.sort_by_key(|&(commit_time, _)| cmp::Reverse(commit_time));
+
+    let abbrev = utils::default_abbrev(store)?;
 
-    let mut commits = vec![];
-    get_commits(hash.trim(), &mut commits)?;
-    commits.sort_by_key(|commit| cmp::Reverse(commit.timestamp));
-    commits.dedup_by(|left, right| left.hash == right.hash);
+    for (_, oid) in history {
+        let commit = cache.commit(&oid)?;
+        let Commit {
+            parents,
+            author,
+            timestamp,
+            timezone,
+            message,
+            ..
+        } = &*commit;
 
-    for Commit {
-        hash,
-        parents,
-        author,
-        timestamp,
-        timezone,
-        message,
-    } in commits
-    {
         if oneline {
             let message = message.replace('\n', " ");
             let message = if message.len() > 40 {
@@ -62,13 +69,15 @@ pub fn log(oneline: bool, hash: Option<&str>, mut output: impl Write) -> anyhow:
             } else {
                 message
             };
-            writeln!(output, "{} {message}", &hash.unwrap()[..7])?;
+            let hash = store.abbreviate(&oid, abbrev)?;
+            writeln!(output, "{hash} {message}")?;
         } else {
-            writeln!(output, "commit {}", hash.unwrap())?;
+            writeln!(output, "commit {oid}")?;
             if parents.len() > 1 {
                 write!(output, "Merge:\t")?;
                 for parent in parents.iter().take(5) {
-                    write!(output, "{} ", std::str::from_utf8(&parent[..7])?)?;
+                    let parent = store.abbreviate(parent, abbrev)?;
+                    write!(output, "{parent} ")?;
                 }
                 writeln!(output)?;
             }
@@ -76,14 +85,14 @@ pub fn log(oneline: bool, hash: Option<&str>, mut output: impl Write) -> anyhow:
             // git log actually displays the date using this using the committer's timezone,
             // but this implementation uses the user's timezone instead.
             let datetime = Local
-                .timestamp_opt(timestamp as i64, 0)
+                .timestamp_opt(*timestamp as i64, 0)
                 .single()
-                .context("failed to create datetime")?;
+                .ok_or_else(|| Error::Other("failed to create datetime".into()))?;
             writeln!(
                 output,
                 "Date:\t{} {}",
                 datetime.format("%a %b %d  %H:%M:%S %Y"),
-                std::str::from_utf8(&timezone)?
+                String::from_utf8_lossy(timezone)
             )?;
             writeln!(output)?;
             let message = message.replace('\n', "\n\t");
@@ -1,10 +1,11 @@
 use std::io::Write;
 
-use anyhow::ensure;
-
 use crate::{
-    utils::{self, EntryDisplay},
-    SHA_LEN,
+    cache::ObjectCache,
+    parsing::Type,
+    revision,
+    store::ObjectStore,
+    utils::{self, Entry, EntryDisplay},
 };
 
 #[derive(clap::Args)]
@@ -21,30 +22,85 @@ pub struct Args {
     #[arg(long)]
     pub name_only: bool,
 
-    /// Abbreviate hashes
-    #[arg(long, default_value_t = SHA_LEN as u8)]
-    pub abbrev: u8,
+    /// Abbreviate hashes to at least this many hex digits, or to core.abbrev if no value is given
+    #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true)]
+    pub abbrev: Option<Option<u8>>,
 
     /// The object's hash
     pub hash: String,
 }
 
+pub enum Abbrev {
+    Full,
+    Default,
+    Min(u8),
+}
+
+impl From<Option<Option<u8>>> for Abbrev {
+    fn from(abbrev: Option<Option<u8>>) -> Self {
+        match abbrev {
+            None => Self::Full,
+            Some(None) => Self::Default,
+            Some(Some(min)) => Self::Min(min),
+        }
+    }
+}
+
 pub fn ls_tree(
+    store: &dyn ObjectStore,
     recurse: bool,
     trees_only: bool,
     name_only: bool,
-    abbrev: u8,
+    abbrev: Abbrev,
     hash: &str,
     mut output: impl Write,
-) -> anyhow::Result<()> {
-    ensure!(abbrev <= SHA_LEN as u8, "abbrev value must be <= {SHA_LEN}");
-
-    for entry in utils::tree_level(hash, recurse)? {
-        entry.display.set(Some(EntryDisplay {
-            trees_only,
-            name_only,
-            abbrev,
-        }));
+) -> crate::Result<()> {
+    let hex_len = store.format().hex_len();
+    let min_len = match abbrev {
+        Abbrev::Full => None,
+        Abbrev::Default => Some(utils::default_abbrev(store)?),
+        Abbrev::Min(min) => {
+            crate::ensure!(min as usize <= hex_len, "abbrev value must be <= {hex_len}");
+            Some(min as usize)
+        }
+    };
+
+    // each hash is abbreviated separately, since some need more digits to be unique
+    fn prepare(
+        store: &dyn ObjectStore,
+        entries: &[Entry],
+        display: EntryDisplay,
+        min_len: Option<usize>,
+    ) -> crate::Result<()> {
+        for entry in entries {
+            let abbrev = match min_len {
+                Some(min_len) => store.abbreviate(&entry.hash, min_len)?.len(),
+                None => entry.hash.format().hex_len(),
+            };
+            entry.display.set(Some(EntryDisplay {
+                abbrev: abbrev as u8,
+                ..display
+            }));
+
+            if let Some(children) = entry.children.as_deref() {
+                prepare(store, children, display, min_len)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    let tree = revision::resolve_as(store, hash, Type::Tree)?;
+    let entries = utils::tree_level(&ObjectCache::open(store)?, &tree, recurse)?;
+    let display = EntryDisplay {
+        trees_only,
+        name_only,
+        abbrev: hex_len as u8,
+    };
+    // names don't need hashes abbreviated
+    prepare(store, &entries, display, min_len.filter(|_| !name_only))?;
+
+    for entry in entries {
         write!(output, "{entry}")?;
     }
 
@@ -0,0 +1,70 @@
+use std::io::{Read, Write};
+
+use crate::{
+    parsing,
+    store::{ObjectStore, Oid},
+    Error,
+};
+
+#[derive(clap::Args)]
+pub struct Args {}
+
+/// Reads a tag from `input`, and writes it to the .git database if it's valid.
+pub fn mktag(
+    store: &dyn ObjectStore,
+    mut input: impl Read,
+    mut output: impl Write,
+) -> crate::Result<()> {
+    let mut contents = vec![];
+    input.read_to_end(&mut contents)?;
+
+    let oid = write_tag(store, &contents)?;
+    writeln!(output, "{oid}")?;
+
+    Ok(())
+}
+
+/// Checks a tag as strictly as `git mktag` does, then stores it as-is.
+pub fn write_tag(store: &dyn ObjectStore, contents: &[u8]) -> crate::Result<Oid> {
+    let (_, tag) = parsing::parse_tag(contents).map_err(|error| {
+        Error::Other(format!("tag on stdin is invalid: {}", Error::from(error)))
+    })?;
+
+    crate::ensure!(
+        tag.tagger.is_some(),
+        "tag on stdin is invalid: expected 'tagger' line"
+    );
+    crate::ensure!(
+        valid_tag_name(&tag.name),
+        "tag on stdin is invalid: invalid 'tag' name: {}",
+        tag.name
+    );
+
+    let header = store
+        .read_header(&tag.object)?
+        .ok_or_else(|| Error::ObjectNotFound(tag.object.to_string()))?;
+    if header.r#type != tag.r#type {
+        return Err(Error::WrongType {
+            oid: tag.object,
+            expected: tag.r#type,
+            found: header.r#type,
+        });
+    }
+
+    store.write(parsing::Type::Tag, contents)
+}
+
+/// Whether `refs/tags/<name>` would be a valid ref
+fn valid_tag_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['-', '.', '/'])
+        && !name.ends_with(['.', '/'])
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.contains("//")
+        && !name.contains("/.")
+        && !name
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+};
+
+use crate::{
+    parsing::Type,
+    store::{ObjectStore, Oid},
+    utils::{self, GITLINK},
+    Error,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Entries are terminated by NUL instead of newline
+    #[arg(short = 'z')]
+    pub nul_terminated: bool,
+}
+
+/// Reads tree entries in ls-tree's format, one per line, and writes a tree
+/// holding them to the .git database.
+pub fn mktree(
+    store: &dyn ObjectStore,
+    nul_terminated: bool,
+    mut input: impl Read,
+    mut output: impl Write,
+) -> crate::Result<()> {
+    let mut contents = String::new();
+    input.read_to_string(&mut contents)?;
+    let terminator = if nul_terminated { '\0' } else { '\n' };
+
+    let mut entries = vec![];
+    for line in contents.split_terminator(terminator) {
+        entries.push(parse_entry(store, line)?);
+    }
+
+    let oid = write_entries(store, entries)?;
+    writeln!(output, "{oid}")?;
+
+    Ok(())
+}
+
+/// Parses "<mode> <type> <hash>\t<name>", checking that the object exists and
+/// has the type its mode calls for. Tabs may also separate the first fields.
+fn parse_entry(store: &dyn ObjectStore, line: &str) -> crate::Result<(u32, String, Oid)> {
+    let mut fields = line.splitn(4, [' ', '\t']);
+    let (Some(mode), Some(r#type), Some(hash), Some(name)) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        crate::bail!("input format error: {line}");
+    };
+
+    let mode = match mode.trim_start_matches('0') {
+        "100644" => 100_644,
+        "100755" => 100_755,
+        "120000" => 120_000,
+        "40000" => 40_000,
+        "160000" => GITLINK,
+        _ => crate::bail!("invalid mode '{mode}' in: {line}"),
+    };
+    let expected = match mode {
+        40_000 => Type::Tree,
+        GITLINK => Type::Commit,
+        _ => Type::Blob,
+    };
+    crate::ensure!(
+        r#type == expected.to_string(),
+        "entry '{name}' object type ({type}) doesn't match mode type ({expected})"
+    );
+    crate::ensure!(
+        !name.is_empty() && !name.contains('/') && name != "." && name != "..",
+        "invalid path '{name}'"
+    );
+
+    let oid = hash
+        .parse()
+        .map_err(|_| Error::Other(format!("input format error: {line}")))?;
+    // submodule commits live in another repository
+    if mode != GITLINK {
+        let header = store
+            .read_header(&oid)?
+            .ok_or_else(|| Error::ObjectNotFound(oid.to_string()))?;
+        crate::ensure!(
+            header.r#type == expected,
+            "entry '{name}' object {oid} is a {}, but the mode calls for a {expected}",
+            header.r#type
+        );
+    }
+
+    Ok((mode, name.to_owned(), oid))
+}
+
+/// Sorts entries in git's tree order and writes them as a tree
+fn write_entries(
+    store: &dyn ObjectStore,
+    mut entries: Vec<(u32, String, Oid)>,
+) -> crate::Result<Oid> {
+    // checked before sorting, since a tree sorts as though its name ended with
+    // '/', so a file and a tree with the same name don't end up next to each other
+    let mut names = HashSet::new();
+    if let Some((_, name, _)) = entries.iter().find(|(_, name, _)| !names.insert(name)) {
+        crate::bail!("duplicate entry '{name}'");
+    }
+
+    entries.sort_by(|(left_mode, left, _), (right_mode, right, _)| {
+        utils::tree_order(
+            (left.as_bytes(), *left_mode == 40_000),
+            (right.as_bytes(), *right_mode == 40_000),
+        )
+    });
+
+    let mut contents = vec![];
+    for (mode, name, oid) in entries {
+        write!(contents, "{mode} {name}\0")?;
+        contents.write_all(oid.as_bytes())?;
+    }
+
+    store.write(Type::Tree, &contents)
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::BTreeSet,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    bitmap,
+    pack::{self, DeltaLimits, Index},
+    revision,
+    store::ObjectStore,
+    utils,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Write the pack to stdout, instead of to files
+    #[arg(long)]
+    pub stdout: bool,
+
+    /// Read revisions from stdin, and pack every object they reach
+    /// that isn't reachable from a revision starting with '^'
+    #[arg(long)]
+    pub revs: bool,
+
+    /// Number of objects to try as the delta base of each object
+    #[arg(long, default_value_t = DeltaLimits::default().window)]
+    pub window: usize,
+
+    /// Longest chain of deltas needed to rebuild an object
+    #[arg(long, default_value_t = DeltaLimits::default().depth)]
+    pub depth: usize,
+
+    /// Also write reachability bitmaps for the pack, which has to include
+    /// everything the revisions reach
+    #[arg(long, requires = "revs", conflicts_with = "stdout")]
+    pub write_bitmap_index: bool,
+
+    /// Write <base-name>-<checksum>.pack and its index
+    #[arg(required_unless_present = "stdout", conflicts_with = "stdout")]
+    pub base_name: Option<PathBuf>,
+}
+
+/// Packs the objects listed in `input`, one per line with an optional name
+/// after a space, or with `revs`, the objects reachable from the revisions and
+/// ranges listed. The pack is written to `output`, unless a `base_name` is given,
+/// in which case it's written with its index to files, and its checksum to `output`.
+/// With `write_bitmaps`, bitmaps for the pack are written next to its index.
+pub fn pack_objects(
+    store: &dyn ObjectStore,
+    revs: bool,
+    limits: DeltaLimits,
+    write_bitmaps: bool,
+    base_name: Option<&Path>,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> crate::Result<()> {
+    let mut objects = vec![];
+    let mut include = vec![];
+    let mut exclude = vec![];
+
+    for line in input.lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+
+        if !revs {
+            let (hash, name) = line.split_once(' ').unwrap_or((&line, ""));
+            objects.push((store.resolve(hash)?, name.to_owned()));
+        } else if let Some((from, to)) = revision::split_range(&line)? {
+            exclude.push(revision::resolve(store, from)?);
+            include.push(revision::resolve(store, to)?);
+        } else if let Some(excluded) = line.strip_prefix('^') {
+            exclude.push(revision::resolve(store, excluded)?);
+        } else {
+            include.push(revision::resolve(store, &line)?);
+        }
+    }
+
+    crate::ensure!(
+        !write_bitmaps || exclude.is_empty(),
+        "bitmaps can't be written for a pack that leaves objects out"
+    );
+
+    if revs {
+        // what the other side has only needs to be enumerated, which bitmaps do
+        // quickly, while the objects to send keep the names that find delta bases
+        let excluded = bitmap::reachable(store, &exclude)?;
+        objects = utils::reachable_objects(store, &include, &excluded)?;
+    } else {
+        // like git, an object listed twice is only packed once
+        let mut seen = BTreeSet::new();
+        objects.retain(|(oid, _)| seen.insert(*oid));
+    }
+
+    let compression = utils::pack_compression()?;
+    let Some(base_name) = base_name else {
+        pack::pack_objects(store, &objects, limits, compression, &mut output)?;
+        return Ok(());
+    };
+
+    let mut pack = vec![];
+    let (entries, checksum) = pack::pack_objects(store, &objects, limits, compression, &mut pack)?;
+
+    // the pack goes into place before the index that readers find it through
+    let name = format!("{}-{checksum}", base_name.display());
+    utils::write_atomic(Path::new(&format!("{name}.pack")), |file| {
+        Ok(file.write_all(&pack)?)
+    })?;
+    utils::write_atomic(Path::new(&format!("{name}.idx")), |file| {
+        pack::write_index(entries, &checksum, file)
+    })?;
+
+    if write_bitmaps {
+        let index = Index::open(format!("{name}.idx"), store.format())?;
+        utils::write_atomic(Path::new(&format!("{name}.bitmap")), |file| {
+            bitmap::write_bitmap(store, &index, &include, file)
+        })?;
+    }
+
+    writeln!(output, "{checksum}")?;
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use std::{io::Write, time::SystemTime};
+
+use crate::{
+    bitmap,
+    store::{Database, ObjectStore},
+    utils,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Only delete objects older than this, such as "2.weeks.ago"
+    #[arg(long, default_value = "now")]
+    pub expire: String,
+
+    /// List the objects that would be deleted, without deleting them
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+/// Deletes loose objects that aren't reachable from the refs, HEAD or reflogs,
+/// and were last written no later than `expire`. Unreachable objects that are
+/// still recent may belong to a command that's running, so they're kept.
+pub fn prune(
+    store: &Database,
+    expire: SystemTime,
+    dry_run: bool,
+    mut output: impl Write,
+) -> crate::Result<()> {
+    let reachable = bitmap::reachable(store, &utils::root_objects()?)?;
+
+    for oid in store.loose().iter()? {
+        if reachable.contains(&oid) || store.loose().modified(&oid)? > expire {
+            continue;
+        }
+
+        if dry_run {
+            let r#type = store.loose().get(&oid)?.r#type;
+            writeln!(output, "{oid} {type}")?;
+        } else {
+            store.loose().remove(&oid)?;
+        }
+    }
+
+    Ok(())
+}
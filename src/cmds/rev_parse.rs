@@ -0,0 +1,58 @@
+use std::io::Write;
+
+use crate::{
+    revision,
+    store::{ObjectStore, Oid},
+    utils,
+};
+
+use super::ls_tree::Abbrev;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Abbreviate hashes to at least this many hex digits, or to core.abbrev if no value is given
+    #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true)]
+    pub short: Option<Option<u8>>,
+
+    /// Revisions to resolve, such as `main~2`, `v1.0^{tree}` or `HEAD:src/main.rs`
+    pub revisions: Vec<String>,
+}
+
+/// Prints the hash of the object each revision names. Like git, `^<rev>` prints
+/// the hash after a '^', and `<from>..<to>` prints `<to>` then `^<from>`.
+pub fn rev_parse(
+    store: &dyn ObjectStore,
+    revisions: &[String],
+    abbrev: Abbrev,
+    mut output: impl Write,
+) -> crate::Result<()> {
+    let hex_len = store.format().hex_len();
+    let min_len = match abbrev {
+        Abbrev::Full => hex_len,
+        Abbrev::Default => utils::default_abbrev(store)?,
+        Abbrev::Min(min) => {
+            crate::ensure!(min as usize <= hex_len, "abbrev value must be <= {hex_len}");
+            min as usize
+        }
+    };
+    let show = |oid: Oid| -> crate::Result<String> {
+        if min_len == hex_len {
+            Ok(oid.to_string())
+        } else {
+            store.abbreviate(&oid, min_len)
+        }
+    };
+
+    for revision in revisions {
+        if let Some((from, to)) = revision::split_range(revision)? {
+            writeln!(output, "{}", show(revision::resolve(store, to)?)?)?;
+            writeln!(output, "^{}", show(revision::resolve(store, from)?)?)?;
+        } else if let Some(excluded) = revision.strip_prefix('^') {
+            writeln!(output, "^{}", show(revision::resolve(store, excluded)?)?)?;
+        } else {
+            writeln!(output, "{}", show(revision::resolve(store, revision)?)?)?;
+        }
+    }
+
+    Ok(())
+}
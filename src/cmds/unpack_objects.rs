@@ -0,0 +1,32 @@
+use std::io::Read;
+
+use crate::{
+    pack::{self, PackReader},
+    store::ObjectStore,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Check the pack without writing any objects
+    #[arg(short = 'n')]
+    pub dry_run: bool,
+}
+
+/// Reads a pack from `input` and writes each of its objects to `store`.
+/// Deltas may use bases that are already in the store, as thin packs do.
+pub fn unpack_objects(
+    store: &dyn ObjectStore,
+    dry_run: bool,
+    mut input: impl Read,
+) -> crate::Result<()> {
+    let mut pack = vec![];
+    input.read_to_end(&mut pack)?;
+    let reader = PackReader::new(&pack, store.format())?;
+
+    pack::resolve_pack(reader, &|base| store.read(base), |object| {
+        if !dry_run {
+            store.write(object.r#type, object.contents)?;
+        }
+        Ok(())
+    })
+}
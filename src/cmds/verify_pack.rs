@@ -0,0 +1,117 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    pack::{self, Index, PackReader},
+    store::ObjectFormat,
+    Error,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// List every object, then how many objects have each length of delta chain
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// The pack index to check, or its packfile
+    pub index: PathBuf,
+}
+
+/// Checks that a pack index matches its packfile, and that every object in the
+/// pack can be read. With `verbose`, lists each object's type, size, size in the
+/// pack and offset, along with the depth and base of deltas.
+pub fn verify_pack(
+    index_path: &Path,
+    verbose: bool,
+    format: ObjectFormat,
+    mut output: impl Write,
+) -> crate::Result<()> {
+    let index_path = index_path.with_extension("idx");
+    let pack_path = index_path.with_extension("pack");
+
+    let data = fs::read(&index_path)?;
+    let hash_len = format.hash_len();
+    crate::ensure!(
+        data.len() >= hash_len,
+        CorruptPack,
+        "pack index is truncated"
+    );
+    let mut hasher = format.hasher();
+    hasher.update(&data[..data.len() - hash_len]);
+    crate::ensure!(
+        hasher.finalize().as_bytes() == &data[data.len() - hash_len..],
+        CorruptPack,
+        "index checksum mismatch for {}",
+        index_path.display()
+    );
+    let index = Index::parse(data, format)?;
+
+    let pack = fs::read(&pack_path)?;
+    let reader = PackReader::new(&pack, format)?;
+    crate::ensure!(
+        index.pack_checksum() == reader.checksum().as_bytes(),
+        CorruptPack,
+        "packfile {} does not match index",
+        pack_path.display()
+    );
+    crate::ensure!(
+        index.len() == reader.len(),
+        CorruptPack,
+        "pack index lists {} objects, but the pack has {}",
+        index.len(),
+        reader.len()
+    );
+
+    // offset, then the line describing the object and its delta depth
+    let mut objects = vec![];
+    pack::resolve_pack(reader, &|_| Ok(None), |object| {
+        let position = index.position(&object.oid).ok_or_else(|| {
+            Error::CorruptPack(format!("object {} is missing from the index", object.oid))
+        })?;
+        crate::ensure!(
+            index.offset(position)? == object.entry.offset
+                && index.crc32(position) == object.entry.crc32,
+            CorruptPack,
+            "index entry for {} does not match the pack",
+            object.oid
+        );
+
+        let mut line = format!(
+            "{} {:<6} {} {} {}",
+            object.oid,
+            object.r#type.to_string(),
+            object.entry.data.len(),
+            object.entry.packed_size,
+            object.entry.offset
+        );
+        if let Some(base) = object.base {
+            line.push_str(&format!(" {} {base}", object.depth));
+        }
+        objects.push((object.entry.offset, line, object.depth));
+        Ok(())
+    })?;
+
+    if verbose {
+        objects.sort_unstable_by_key(|(offset, ..)| *offset);
+        let mut depths = BTreeMap::<_, usize>::new();
+        for (_, line, depth) in &objects {
+            writeln!(output, "{line}")?;
+            *depths.entry(*depth).or_default() += 1;
+        }
+
+        let plural = |count| if count == 1 { "object" } else { "objects" };
+        for (depth, count) in depths {
+            match depth {
+                0 => writeln!(output, "non delta: {count} {}", plural(count))?,
+                _ => writeln!(output, "chain length = {depth}: {count} {}", plural(count))?,
+            }
+        }
+        writeln!(output, "{}: ok", pack_path.display())?;
+    }
+
+    Ok(())
+}
@@ -1,16 +1,10 @@
-use std::{
-    borrow::Borrow,
-    ffi::OsString,
-    fs,
-    io::{self, Read, Write},
-    os::unix::fs::PermissionsExt,
-    path::Path,
-};
-
-use flate2::read::ZlibEncoder;
-use sha1::{Digest, Sha1};
+use std::{borrow::Borrow, ffi::OsString, fs, io::Write, os::unix::fs::PermissionsExt, path::Path};
 
-use crate::{utils, SHA_LEN};
+use crate::{
+    parsing::Type,
+    store::{ObjectStore, Oid},
+    utils,
+};
 
 const IGNORE: &[&str] = &[".git", ".vscode", "target"];
 
@@ -20,21 +14,18 @@ pub struct Args {}
 struct Entry {
     mode: u32,
     name: OsString,
-    hash: [u8; SHA_LEN],
+    hash: Oid,
 }
 
-pub fn write_tree(mut output: impl Write) -> anyhow::Result<()> {
-    let hash = write_tree_at(".")?;
+pub fn write_tree(store: &dyn ObjectStore, mut output: impl Write) -> crate::Result<()> {
+    let oid = write_tree_at(store, ".")?;
 
-    for byte in hash {
-        write!(output, "{byte:02x}")?;
-    }
-    writeln!(output)?;
+    writeln!(output, "{oid}")?;
 
     Ok(())
 }
 
-fn write_tree_at(path: impl AsRef<Path>) -> anyhow::Result<[u8; SHA_LEN]> {
+fn write_tree_at(store: &dyn ObjectStore, path: impl AsRef<Path>) -> crate::Result<Oid> {
     let entries = {
         let mut entries = vec![];
 
@@ -47,17 +38,19 @@ fn write_tree_at(path: impl AsRef<Path>) -> anyhow::Result<[u8; SHA_LEN]> {
                     Entry {
                         mode: 40_000,
                         name,
-                        hash: write_tree_at(entry.path())?,
+                        hash: write_tree_at(store, entry.path())?,
                     }
                 } else {
-                    let mut hash = [0u8; SHA_LEN];
+                    let mut hash = vec![];
                     super::hash_object::hash_object(
+                        store,
                         true,
                         super::hash_object::Type::Blob,
                         super::hash_object::Source::Path(entry.path()),
                         false,
-                        hash.as_mut(),
+                        &mut hash,
                     )?;
+                    let hash = Oid::from_bytes(&hash)?;
                     let metadata = entry.metadata()?;
                     let permissions = metadata.permissions().mode();
                     let mode = if metadata.is_symlink() {
@@ -74,15 +67,10 @@ fn write_tree_at(path: impl AsRef<Path>) -> anyhow::Result<[u8; SHA_LEN]> {
         }
 
         entries.sort_unstable_by(|left, right| {
-            let (left, right) = (left.name.as_encoded_bytes(), right.name.as_encoded_bytes());
-            let small_len = left.len().min(right.len());
-
-            if left[..small_len] == right[..small_len] {
-                // git prefers this edge case to be reversed for some reason
-                left.len().cmp(&right.len()).reverse()
-            } else {
-                left.cmp(right)
-            }
+            utils::tree_order(
+                (left.name.as_encoded_bytes(), left.mode == 40_000),
+                (right.name.as_encoded_bytes(), right.mode == 40_000),
+            )
         });
         entries
     };
@@ -93,24 +81,8 @@ fn write_tree_at(path: impl AsRef<Path>) -> anyhow::Result<[u8; SHA_LEN]> {
         write!(contents, "{} ", entry.mode)?;
         contents.write_all(entry.name.as_encoded_bytes())?;
         contents.write_all(b"\0")?;
-        contents.write_all(&entry.hash)?;
+        contents.write_all(entry.hash.as_bytes())?;
     }
 
-    let header = format!("tree {}\0", contents.len());
-
-    let mut hasher = Sha1::new();
-    io::copy(
-        &mut header.as_bytes().chain(contents.as_slice()),
-        &mut hasher,
-    )?;
-    let hash = hasher.finalize();
-
-    let mut file = utils::create_object(&hash.into())?;
-    let mut compressor = ZlibEncoder::new(
-        header.as_bytes().chain(contents.as_slice()),
-        Default::default(), // default compression is level 6
-    );
-    io::copy(&mut compressor, &mut file)?;
-
-    Ok(hash.into())
+    store.write(Type::Tree, &contents)
 }
@@ -0,0 +1,428 @@
+//! The commit-graph file, which records the parents, dates and generation
+//! numbers of commits so that history walks don't need to parse them
+
+use std::{
+    cmp,
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    cache::ObjectCache,
+    parsing::{self, Type},
+    store::{ObjectFormat, ObjectStore, Oid},
+    Error,
+};
+
+const SIGNATURE: &[u8] = b"CGPH";
+const VERSION: u8 = 1;
+
+const OID_FANOUT: &[u8; 4] = b"OIDF";
+const OID_LOOKUP: &[u8; 4] = b"OIDL";
+const COMMIT_DATA: &[u8; 4] = b"CDAT";
+const EXTRA_EDGES: &[u8; 4] = b"EDGE";
+
+/// Parent position of a commit without that many parents
+const NO_PARENT: u32 = 0x7000_0000;
+/// Marks the second parent as a position in the extra edges,
+/// and the last of a commit's extra edges
+const EXTRA_EDGE: u32 = 0x8000_0000;
+/// Generation numbers are stored in 30 bits
+const MAX_GENERATION: u32 = 0x3fff_ffff;
+
+/// What the commit-graph records about a commit
+pub struct GraphCommit {
+    pub tree: Oid,
+    pub parents: Vec<Oid>,
+    /// One more than the highest generation of its parents, starting at 1
+    pub generation: u32,
+    pub commit_time: u64,
+}
+
+/// A commit-graph file, mapping commit ids to their parents and dates
+pub struct CommitGraph {
+    data: Vec<u8>,
+    count: usize,
+    format: ObjectFormat,
+    fanout: usize,
+    lookup: usize,
+    commits: usize,
+    edges: Option<(usize, usize)>,
+}
+
+impl CommitGraph {
+    /// Reads a commit-graph file, if there is one
+    pub fn open(path: impl AsRef<Path>, format: ObjectFormat) -> crate::Result<Option<Self>> {
+        let path = path.as_ref();
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        Self::parse(data, format)
+            .map(Some)
+            .map_err(|error| Error::in_file(path, error))
+    }
+
+    /// Parses a commit-graph whose hashes are in the given format
+    pub fn parse(data: Vec<u8>, format: ObjectFormat) -> crate::Result<Self> {
+        let hash_len = format.hash_len();
+        crate::ensure!(
+            data.len() >= 8 + hash_len && data.starts_with(SIGNATURE),
+            CorruptPack,
+            "invalid commit-graph signature"
+        );
+        crate::ensure!(
+            data[4] == VERSION,
+            CorruptPack,
+            "unsupported commit-graph version {}",
+            data[4]
+        );
+        crate::ensure!(
+            data[5] == hash_version(format),
+            CorruptPack,
+            "commit-graph hash version {} does not match the repository",
+            data[5]
+        );
+        crate::ensure!(
+            data[7] == 0,
+            CorruptPack,
+            "split commit-graphs are not supported"
+        );
+
+        let (contents, checksum) = data.split_at(data.len() - hash_len);
+        let mut hasher = format.hasher();
+        hasher.update(contents);
+        crate::ensure!(
+            hasher.finalize().as_bytes() == checksum,
+            CorruptPack,
+            "commit-graph checksum does not match"
+        );
+
+        // each chunk ends where the next one starts, and the last entry only marks the end
+        let chunks = data[6] as usize;
+        let table = data
+            .get(8..8 + (chunks + 1) * 12)
+            .ok_or_else(|| Error::CorruptPack("commit-graph is truncated".into()))?;
+        let mut offsets = HashMap::new();
+        for (entry, next) in table.chunks(12).zip(table.chunks(12).skip(1)) {
+            let start = u64::from_be_bytes(entry[4..].try_into().expect("8 bytes")) as usize;
+            let end = u64::from_be_bytes(next[4..].try_into().expect("8 bytes")) as usize;
+            crate::ensure!(
+                start <= end && end <= data.len() - hash_len,
+                CorruptPack,
+                "commit-graph chunk is out of bounds"
+            );
+            offsets.insert(
+                <[u8; 4]>::try_from(&entry[..4]).expect("4 bytes"),
+                (start, end),
+            );
+        }
+
+        let chunk = |id: &[u8; 4]| {
+            offsets.get(id).copied().ok_or_else(|| {
+                Error::CorruptPack(format!(
+                    "commit-graph is missing its {} chunk",
+                    id.escape_ascii()
+                ))
+            })
+        };
+        let (fanout, fanout_end) = chunk(OID_FANOUT)?;
+        let (lookup, lookup_end) = chunk(OID_LOOKUP)?;
+        let (commits, commits_end) = chunk(COMMIT_DATA)?;
+        let mut graph = Self {
+            data,
+            count: 0,
+            format,
+            fanout,
+            lookup,
+            commits,
+            edges: offsets.get(EXTRA_EDGES).copied(),
+        };
+
+        crate::ensure!(
+            fanout_end - fanout == 256 * 4,
+            CorruptPack,
+            "commit-graph fanout has the wrong size"
+        );
+        // lookups search between neighbouring fanout entries, so they must never decrease
+        for byte in 1..256 {
+            crate::ensure!(
+                graph.read_u32(fanout + (byte - 1) * 4) <= graph.read_u32(fanout + byte * 4),
+                CorruptPack,
+                "commit-graph fanout is not in order"
+            );
+        }
+        graph.count = graph.read_u32(fanout + 255 * 4) as usize;
+        crate::ensure!(
+            lookup_end - lookup == graph.count * hash_len
+                && commits_end - commits == graph.count * (hash_len + 16),
+            CorruptPack,
+            "commit-graph chunks don't match its number of commits"
+        );
+
+        Ok(graph)
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn oid(&self, position: usize) -> Oid {
+        let len = self.format.hash_len();
+        Oid::from_bytes(&self.data[self.lookup + position * len..][..len])
+            .expect("hash has the format's length")
+    }
+
+    /// Position of a commit in the graph, if it's there
+    pub fn position(&self, oid: &Oid) -> Option<usize> {
+        let first_byte = oid.as_bytes()[0] as usize;
+        let mut start = match first_byte {
+            0 => 0,
+            _ => self.read_u32(self.fanout + (first_byte - 1) * 4) as usize,
+        };
+        let mut end = self.read_u32(self.fanout + first_byte * 4) as usize;
+        while start < end {
+            let middle = (start + end) / 2;
+            match self.oid(middle).cmp(oid) {
+                cmp::Ordering::Less => start = middle + 1,
+                cmp::Ordering::Greater => end = middle,
+                cmp::Ordering::Equal => return Some(middle),
+            }
+        }
+        None
+    }
+
+    /// What the graph records about a commit, if it's there
+    pub fn get(&self, oid: &Oid) -> crate::Result<Option<GraphCommit>> {
+        let Some(position) = self.position(oid) else {
+            return Ok(None);
+        };
+
+        let hash_len = self.format.hash_len();
+        let at = self.commits + position * (hash_len + 16);
+        let tree = Oid::from_bytes(&self.data[at..at + hash_len])?;
+        let first_parent = self.read_u32(at + hash_len);
+        let second_parent = self.read_u32(at + hash_len + 4);
+        let generation = self.read_u32(at + hash_len + 8);
+        let commit_time = self.read_u32(at + hash_len + 12);
+
+        let mut parents = vec![];
+        if first_parent != NO_PARENT {
+            parents.push(self.parent(first_parent)?);
+        }
+        if second_parent & EXTRA_EDGE != 0 {
+            // octopus merges list their other parents in the extra edges
+            let (start, end) = self.edges.ok_or_else(|| {
+                Error::CorruptPack("commit-graph is missing its EDGE chunk".into())
+            })?;
+            let mut at = start + (second_parent & !EXTRA_EDGE) as usize * 4;
+            loop {
+                crate::ensure!(
+                    at + 4 <= end,
+                    CorruptPack,
+                    "commit-graph extra edges are truncated"
+                );
+                let edge = self.read_u32(at);
+                parents.push(self.parent(edge & !EXTRA_EDGE)?);
+                if edge & EXTRA_EDGE != 0 {
+                    break;
+                }
+                at += 4;
+            }
+        } else if second_parent != NO_PARENT {
+            parents.push(self.parent(second_parent)?);
+        }
+
+        Ok(Some(GraphCommit {
+            tree,
+            parents,
+            generation: generation >> 2,
+            commit_time: ((generation & 0b11) as u64) << 32 | commit_time as u64,
+        }))
+    }
+
+    fn parent(&self, position: u32) -> crate::Result<Oid> {
+        crate::ensure!(
+            (position as usize) < self.count,
+            CorruptPack,
+            "commit-graph parent position {position} is out of bounds"
+        );
+        Ok(self.oid(position as usize))
+    }
+
+    fn read_u32(&self, at: usize) -> u32 {
+        u32::from_be_bytes(self.data[at..at + 4].try_into().expect("4 bytes"))
+    }
+}
+
+fn hash_version(format: ObjectFormat) -> u8 {
+    match format {
+        ObjectFormat::Sha1 => 1,
+        ObjectFormat::Sha256 => 2,
+    }
+}
+
+/// Writes a commit-graph of every commit reachable from `tips`, returning how many
+/// there are. Tips that aren't commits or tags pointing to commits are skipped.
+pub fn write_commit_graph(
+    store: &dyn ObjectStore,
+    tips: &[Oid],
+    mut output: impl Write,
+) -> crate::Result<usize> {
+    let format = store.format();
+    let hash_len = format.hash_len();
+    let cache = ObjectCache::open(store)?;
+
+    let mut commits = HashMap::new();
+    let mut stack = vec![];
+    for tip in tips {
+        let mut oid = *tip;
+        loop {
+            let object = store.get(&oid)?;
+            match object.r#type {
+                Type::Commit => stack.push(oid),
+                Type::Tag => {
+                    oid = parsing::parse_tag(&object.contents)
+                        .map_err(|error| Error::corrupt(oid, error))?
+                        .1
+                        .object;
+                    continue;
+                }
+                Type::Tree | Type::Blob => {}
+            }
+            break;
+        }
+    }
+    while let Some(oid) = stack.pop() {
+        if commits.contains_key(&oid) {
+            continue;
+        }
+        let commit = cache.commit(&oid)?;
+        stack.extend(commit.parents.iter().copied());
+        commits.insert(oid, commit);
+    }
+
+    let mut oids = commits.keys().copied().collect::<Vec<_>>();
+    oids.sort_unstable();
+    let positions = oids
+        .iter()
+        .enumerate()
+        .map(|(position, oid)| (*oid, position as u32))
+        .collect::<HashMap<_, _>>();
+
+    // parents need their generations before their children, so each
+    // commit waits on the stack until its parents are done
+    let mut generations = HashMap::new();
+    for oid in &oids {
+        let mut stack = vec![*oid];
+        while let Some(&oid) = stack.last() {
+            if generations.contains_key(&oid) {
+                stack.pop();
+                continue;
+            }
+            let parents = &commits[&oid].parents;
+            let pending = parents
+                .iter()
+                .filter(|parent| !generations.contains_key(*parent))
+                .copied()
+                .collect::<Vec<_>>();
+            if pending.is_empty() {
+                let generation = parents
+                    .iter()
+                    .map(|parent| generations[parent])
+                    .max()
+                    .unwrap_or(0);
+                generations.insert(oid, cmp::min(generation + 1, MAX_GENERATION));
+                stack.pop();
+            } else {
+                stack.extend(pending);
+            }
+        }
+    }
+
+    let mut fanout = [0u32; 256];
+    for oid in &oids {
+        fanout[oid.as_bytes()[0] as usize] += 1;
+    }
+    for byte in 1..256 {
+        fanout[byte] += fanout[byte - 1];
+    }
+
+    let mut lookup = Vec::with_capacity(oids.len() * hash_len);
+    let mut data = Vec::with_capacity(oids.len() * (hash_len + 16));
+    let mut edges = vec![];
+    for oid in &oids {
+        let commit = &commits[oid];
+        lookup.extend_from_slice(oid.as_bytes());
+        data.extend_from_slice(commit.tree.as_bytes());
+
+        let parents = commit
+            .parents
+            .iter()
+            .map(|parent| positions[parent])
+            .collect::<Vec<_>>();
+        let (first_parent, second_parent) = match parents.as_slice() {
+            [] => (NO_PARENT, NO_PARENT),
+            [first] => (*first, NO_PARENT),
+            [first, second] => (*first, *second),
+            [first, rest @ ..] => {
+                let start = edges.len() as u32;
+                edges.extend_from_slice(rest);
+                *edges.last_mut().expect("octopus merges have extra parents") |= EXTRA_EDGE;
+                (*first, start | EXTRA_EDGE)
+            }
+        };
+        data.extend_from_slice(&first_parent.to_be_bytes());
+        data.extend_from_slice(&second_parent.to_be_bytes());
+
+        let commit_time = commit.commit_time as u64;
+        let generation = generations[oid] << 2 | (commit_time >> 32) as u32 & 0b11;
+        data.extend_from_slice(&generation.to_be_bytes());
+        data.extend_from_slice(&(commit_time as u32).to_be_bytes());
+    }
+
+    let mut chunks = vec![
+        (
+            OID_FANOUT,
+            fanout
+                .iter()
+                .flat_map(|count| count.to_be_bytes())
+                .collect(),
+        ),
+        (OID_LOOKUP, lookup),
+        (COMMIT_DATA, data),
+    ];
+    if !edges.is_empty() {
+        let edges = edges.iter().flat_map(|edge| edge.to_be_bytes()).collect();
+        chunks.push((EXTRA_EDGES, edges));
+    }
+
+    let mut graph = vec![];
+    graph.extend_from_slice(SIGNATURE);
+    graph.extend_from_slice(&[VERSION, hash_version(format), chunks.len() as u8, 0]);
+    let mut offset = (8 + (chunks.len() + 1) * 12) as u64;
+    for (id, chunk) in &chunks {
+        graph.extend_from_slice(*id);
+        graph.extend_from_slice(&offset.to_be_bytes());
+        offset += chunk.len() as u64;
+    }
+    graph.extend_from_slice(&[0; 4]);
+    graph.extend_from_slice(&offset.to_be_bytes());
+    for (_, chunk) in &chunks {
+        graph.extend_from_slice(chunk);
+    }
+
+    let mut hasher = format.hasher();
+    hasher.update(&graph);
+    output.write_all(&graph)?;
+    output.write_all(hasher.finalize().as_bytes())?;
+
+    Ok(oids.len())
+}
@@ -0,0 +1,216 @@
+//! The errors this crate returns, which tell apart the failures that callers
+//! handle differently, and the exit codes the command line reports them with
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{parsing::Type, store::Oid};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// No object has this hash, or abbreviated hash
+    ObjectNotFound(String),
+    /// An abbreviated hash matches more than one object, listed with their
+    /// types if they could be read
+    AmbiguousId {
+        prefix: String,
+        candidates: Vec<(Oid, Option<Type>)>,
+    },
+    /// An object, or the data it was read from, can't be parsed
+    CorruptObject {
+        oid: Option<Oid>,
+        reason: String,
+    },
+    /// A packfile, or a file that indexes the objects like a pack index,
+    /// bitmap or commit-graph, can't be parsed
+    CorruptPack(String),
+    /// An object is used as a type it isn't, like a blob named as a commit
+    WrongType {
+        oid: Oid,
+        expected: Type,
+        found: Type,
+    },
+    /// A ref or revision that can't be read or doesn't name anything
+    InvalidRef {
+        name: String,
+        reason: String,
+    },
+    /// There's no repository where one was expected
+    NotARepository(PathBuf),
+    /// A config key or value that can't be used
+    Config(String),
+    /// A remote repository sent or expected something we don't understand
+    Protocol(String),
+    /// A check found problems, which were already reported. `status` is
+    /// what git exits with, like fsck's bitmask of the kinds of problems.
+    ChecksFailed {
+        status: u8,
+        reason: String,
+    },
+    Io(io::Error),
+    /// Anything else, such as invalid input
+    Other(String),
+}
+
+impl Error {
+    /// A parsing error in the object with this id
+    pub fn corrupt(oid: Oid, error: impl Into<Self>) -> Self {
+        match error.into() {
+            Self::CorruptObject { oid: None, reason } => Self::CorruptObject {
+                oid: Some(oid),
+                reason,
+            },
+            error => error,
+        }
+    }
+
+    /// Names the file a corrupt pack error was found in
+    pub fn in_file(path: &Path, error: Self) -> Self {
+        match error {
+            Self::CorruptPack(reason) => Self::CorruptPack(format!("{}: {reason}", path.display())),
+            error => error,
+        }
+    }
+
+    pub fn invalid_ref(name: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self::InvalidRef {
+            name: name.into(),
+            reason: reason.into(),
+        }
+    }
+
+    /// The status git exits with for this error: the checks' own status when
+    /// they fail, 1 for config errors like `git config` does, and 128 for
+    /// everything else, which git reports with `fatal:`
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::ChecksFailed { status, .. } => *status,
+            Self::Config(_) => 1,
+            _ => 128,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ObjectNotFound(name) => write!(f, "failed to find {name}"),
+            Self::AmbiguousId { prefix, candidates } => {
+                write!(f, "short object ID {prefix} is ambiguous")?;
+                write!(f, "\nhint: The candidates are:")?;
+                for (oid, r#type) in candidates {
+                    // every object sharing a longer prefix is a candidate too,
+                    // so they're the only ones to tell apart
+                    let hash = oid.to_string();
+                    let len = (prefix.len() + 1..hash.len())
+                        .find(|&len| {
+                            candidates.iter().all(|(other, _)| {
+                                other == oid || !other.to_string().starts_with(&hash[..len])
+                            })
+                        })
+                        .unwrap_or(hash.len());
+                    match r#type {
+                        Some(r#type) => write!(f, "\nhint:   {} {type}", &hash[..len])?,
+                        None => write!(f, "\nhint:   {} unknown", &hash[..len])?,
+                    }
+                }
+                Ok(())
+            }
+            Self::CorruptObject {
+                oid: Some(oid),
+                reason,
+            } => write!(f, "object {oid} is corrupt: {reason}"),
+            Self::CorruptObject { oid: None, reason } => write!(f, "corrupt object: {reason}"),
+            Self::WrongType {
+                oid,
+                expected,
+                found,
+            } => write!(f, "object {oid} is a {found}, not a {expected}"),
+            Self::InvalidRef { name, reason } => write!(f, "'{name}': {reason}"),
+            Self::NotARepository(path) => {
+                write!(f, "not a git repository: {}", path.display())
+            }
+            Self::CorruptPack(message)
+            | Self::Config(message)
+            | Self::ChecksFailed {
+                reason: message, ..
+            }
+            | Self::Other(message) => write!(f, "{message}"),
+            Self::Protocol(message) => write!(f, "protocol error: {message}"),
+            Self::Io(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<nom::Err<Error>> for Error {
+    fn from(error: nom::Err<Error>) -> Self {
+        match error {
+            nom::Err::Error(error) | nom::Err::Failure(error) => error,
+            nom::Err::Incomplete(_) => Self::CorruptObject {
+                oid: None,
+                reason: "unexpected end of data".into(),
+            },
+        }
+    }
+}
+
+impl<I> nom::error::ParseError<I> for Error {
+    fn from_error_kind(_input: I, kind: nom::error::ErrorKind) -> Self {
+        Self::CorruptObject {
+            oid: None,
+            reason: kind.description().into(),
+        }
+    }
+
+    fn append(_input: I, _kind: nom::error::ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        Self::Protocol(error.to_string())
+    }
+}
+
+/// Returns early with an error formatted like `format!`, which is an
+/// `Error::Other` unless another variant holding a message is named first
+macro_rules! bail {
+    ($variant:ident, $($arg:tt)+) => {
+        return Err($crate::Error::$variant(format!($($arg)+)))
+    };
+    ($($arg:tt)+) => {
+        return Err($crate::Error::Other(format!($($arg)+)))
+    };
+}
+
+/// Returns early with an error like `bail!`, unless the condition holds
+macro_rules! ensure {
+    ($condition:expr, $($arg:tt)+) => {
+        if !$condition {
+            $crate::error::bail!($($arg)+);
+        }
+    };
+}
+
+pub(crate) use {bail, ensure};
@@ -0,0 +1,38 @@
+//! A simple clone of git
+
+#[cfg(test)]
+use std::sync::Mutex;
+
+pub mod bitmap;
+pub mod cache;
+pub mod cmds;
+pub mod commit_graph;
+pub mod error;
+pub mod pack;
+pub mod parsing;
+pub mod revision;
+pub mod store;
+pub mod utils;
+
+pub(crate) use error::{bail, ensure};
+pub use error::{Error, Result};
+
+pub const DOT_GIT: &str = ".git";
+pub const OBJECTS: &str = "objects";
+pub const PACK: &str = "pack";
+pub const REFS: &str = "refs";
+pub const HEADS: &str = "heads";
+pub const TAGS: &str = "tags";
+pub const HEAD: &str = "HEAD";
+pub const CONFIG: &str = "config";
+pub const PACKED_REFS: &str = "packed-refs";
+pub const LOGS: &str = "logs";
+pub const INFO: &str = "info";
+pub const ALTERNATES: &str = "alternates";
+pub const COMMIT_GRAPH: &str = "commit-graph";
+
+/// Length of the longest supported hash, SHA-256
+pub const MAX_SHA_LEN: usize = 32;
+
+#[cfg(test)]
+static FORCE_SINGLE_THREAD: Mutex<()> = Mutex::new(()); // used to synchronize unit tests
@@ -1,27 +1,8 @@
-use std::{io, path::Path};
+use std::{io, path::Path, process::ExitCode, time::SystemTime};
 
 use clap::{Parser, Subcommand};
 
-#[cfg(test)]
-use std::sync::Mutex;
-
-mod cmds;
-mod parsing;
-mod utils;
-
-const DOT_GIT: &str = ".git";
-const OBJECTS: &str = "objects";
-const REFS: &str = "refs";
-const HEADS: &str = "heads";
-const TAGS: &str = "tags";
-const HEAD: &str = "HEAD";
-const CONFIG: &str = "config";
-
-const SHA_LEN: usize = 20;
-const SHA_DISPLAY_LEN: usize = 40;
-
-#[cfg(test)]
-static FORCE_SINGLE_THREAD: Mutex<()> = Mutex::new(()); // used to synchronize unit tests
+use git_starter_rust::{cmds, pack::DeltaLimits, store, utils, Result, DOT_GIT, OBJECTS};
 
 /// A simple clone of git
 #[derive(Parser)]
@@ -50,7 +31,7 @@ enum Cmd {
     /// Print information about an object
     CatFile(cmds::cat_file::Args),
 
-    /// Compute SHA hash of an object
+    /// Compute the hash of an object
     HashObject(cmds::hash_object::Args),
 
     /// List tree object contents
@@ -61,26 +42,84 @@ enum Cmd {
 
     /// Create a commit object
     CommitTree(cmds::commit_tree::Args),
+
+    /// Print the hashes of the objects that revisions name
+    RevParse(cmds::rev_parse::Args),
+
+    /// Create a tag object from stdin, after checking it
+    Mktag(cmds::mktag::Args),
+
+    /// Create a tree object from ls-tree formatted entries on stdin
+    Mktree(cmds::mktree::Args),
+
+    /// Write a commit-graph file to speed up history walks
+    CommitGraph(cmds::commit_graph::Args),
+
+    /// Verify the objects in the database and their connectivity
+    Fsck(cmds::fsck::Args),
+
+    /// Pack reachable objects into a single pack, and remove the redundant ones
+    #[command(alias = "repack")]
+    Gc(cmds::gc::Args),
+
+    /// Delete unreachable loose objects
+    Prune(cmds::prune::Args),
+
+    /// Count loose objects and the disk space they use
+    CountObjects(cmds::count_objects::Args),
+
+    /// Build an index for a packfile
+    IndexPack(cmds::index_pack::Args),
+
+    /// Check a packfile against its index
+    VerifyPack(cmds::verify_pack::Args),
+
+    /// Write the objects of a pack from stdin as loose objects
+    UnpackObjects(cmds::unpack_objects::Args),
+
+    /// Write a packfile of the objects or revisions listed on stdin
+    PackObjects(cmds::pack_objects::Args),
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            // like git, only errors that it exits with 128 for are fatal
+            let code = error.exit_code();
+            let prefix = if code == 128 { "fatal" } else { "error" };
+            eprintln!("{prefix}: {error}");
+            ExitCode::from(code)
+        }
+    }
 }
 
-fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+fn run(cli: Cli) -> Result<()> {
     let stdout = io::stdout().lock();
+    let database = || store::Database::open(Path::new(DOT_GIT).join(OBJECTS));
 
     match cli.cmd {
-        Cmd::Init(cmds::init::Args { path }) => {
-            cmds::init::init(path.unwrap_or_else(|| ".".into()), stdout)
-        }
-
-        Cmd::CatFile(cmds::cat_file::Args { info, hash }) => {
-            cmds::cat_file::cat_file(info.into(), &hash, stdout)
-        }
+        Cmd::Init(cmds::init::Args {
+            object_format,
+            path,
+        }) => cmds::init::init(path.unwrap_or_else(|| ".".into()), object_format, stdout),
+
+        Cmd::CatFile(args) => match args.into() {
+            cmds::cat_file::Request::Object(info, hash) => {
+                cmds::cat_file::cat_file(&database()?, info, &hash, stdout)
+            }
+            cmds::cat_file::Request::Batch(batch) => {
+                cmds::cat_file::cat_file_batch(&database()?, batch, io::stdin().lock(), stdout)
+            }
+        },
 
         Cmd::HashObject(cmds::hash_object::Args {
             write,
             r#type,
             source,
-        }) => cmds::hash_object::hash_object(write, r#type, source.into(), true, stdout),
+        }) => {
+            cmds::hash_object::hash_object(&database()?, write, r#type, source.into(), true, stdout)
+        }
 
         Cmd::LsTree(cmds::ls_tree::Args {
             recurse,
@@ -88,26 +127,129 @@ fn main() -> anyhow::Result<()> {
             name_only,
             abbrev,
             hash,
-        }) => cmds::ls_tree::ls_tree(recurse, trees_only, name_only, abbrev, &hash, stdout),
+        }) => cmds::ls_tree::ls_tree(
+            &database()?,
+            recurse,
+            trees_only,
+            name_only,
+            abbrev.into(),
+            &hash,
+            stdout,
+        ),
 
-        Cmd::WriteTree(cmds::write_tree::Args {}) => cmds::write_tree::write_tree(stdout),
+        Cmd::WriteTree(cmds::write_tree::Args {}) => {
+            cmds::write_tree::write_tree(&database()?, stdout)
+        }
 
         Cmd::CommitTree(cmds::commit_tree::Args {
             parents,
             message,
             tree_hash,
-        }) => cmds::commit_tree::commit_tree(&parents, &message, tree_hash.as_deref(), stdout),
+        }) => cmds::commit_tree::commit_tree(
+            &database()?,
+            &parents,
+            &message,
+            tree_hash.as_deref(),
+            stdout,
+        ),
+
+        Cmd::RevParse(cmds::rev_parse::Args { short, revisions }) => {
+            cmds::rev_parse::rev_parse(&database()?, &revisions, short.into(), stdout)
+        }
+
+        Cmd::Mktag(cmds::mktag::Args {}) => {
+            cmds::mktag::mktag(&database()?, io::stdin().lock(), stdout)
+        }
+
+        Cmd::CommitGraph(cmds::commit_graph::Args { action }) => match action {
+            cmds::commit_graph::Action::Write => {
+                cmds::commit_graph::write(&database()?, &Path::new(DOT_GIT).join(OBJECTS))
+            }
+        },
+
+        Cmd::Fsck(cmds::fsck::Args {
+            unreachable,
+            no_dangling,
+        }) => cmds::fsck::fsck(
+            &database()?,
+            unreachable,
+            !no_dangling,
+            stdout,
+            io::stderr(),
+        ),
+
+        Cmd::Gc(cmds::gc::Args { window, depth }) => {
+            cmds::gc::gc(&database()?, DeltaLimits { window, depth })
+        }
+
+        Cmd::Prune(cmds::prune::Args { expire, dry_run }) => {
+            let expire = utils::parse_expiry(&expire, SystemTime::now())?;
+            cmds::prune::prune(&database()?, expire, dry_run, stdout)
+        }
+
+        Cmd::CountObjects(cmds::count_objects::Args { verbose }) => {
+            cmds::count_objects::count_objects(
+                &database()?,
+                &Path::new(DOT_GIT).join(OBJECTS),
+                verbose,
+                stdout,
+            )
+        }
+
+        Cmd::IndexPack(cmds::index_pack::Args { output, pack }) => {
+            cmds::index_pack::index_pack(&pack, output.as_deref(), utils::object_format()?, stdout)
+        }
+
+        Cmd::VerifyPack(cmds::verify_pack::Args { verbose, index }) => {
+            cmds::verify_pack::verify_pack(&index, verbose, utils::object_format()?, stdout)
+        }
+
+        Cmd::UnpackObjects(cmds::unpack_objects::Args { dry_run }) => {
+            cmds::unpack_objects::unpack_objects(&database()?, dry_run, io::stdin().lock())
+        }
+
+        Cmd::PackObjects(cmds::pack_objects::Args {
+            stdout: _,
+            revs,
+            window,
+            depth,
+            write_bitmap_index,
+            base_name,
+        }) => cmds::pack_objects::pack_objects(
+            &database()?,
+            revs,
+            DeltaLimits { window, depth },
+            write_bitmap_index,
+            base_name.as_deref(),
+            io::stdin().lock(),
+            stdout,
+        ),
+
+        Cmd::Mktree(cmds::mktree::Args { nul_terminated }) => {
+            cmds::mktree::mktree(&database()?, nul_terminated, io::stdin().lock(), stdout)
+        }
 
         Cmd::Config(args) => cmds::config::config(args.into(), stdout),
 
-        Cmd::Commit(cmds::commit::Args { message }) => cmds::commit::commit(message, stdout),
+        Cmd::Commit(cmds::commit::Args { message }) => {
+            cmds::commit::commit(&database()?, message, stdout)
+        }
 
         Cmd::Log(cmds::log::Args { oneline, hash }) => {
-            cmds::log::log(oneline, hash.as_deref(), stdout)
+            cmds::log::log(&database()?, oneline, hash.as_deref(), stdout)
         }
 
-        Cmd::Clone(cmds::clone::Args { remote, path }) => {
-            cmds::clone::clone(&remote, path.as_deref().unwrap_or(Path::new(".")), stdout)
-        }
+        Cmd::Clone(cmds::clone::Args {
+            remote,
+            path,
+            shared,
+            reference,
+        }) => cmds::clone::clone(
+            &remote,
+            path.as_deref().unwrap_or(Path::new(".")),
+            shared,
+            reference.as_deref(),
+            stdout,
+        ),
     }
 }
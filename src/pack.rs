@@ -0,0 +1,913 @@
+use std::{
+    cmp,
+    collections::{HashMap, VecDeque},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use flate2::{bufread::ZlibDecoder, read::ZlibEncoder, Compression, Crc};
+
+use crate::{
+    parsing::{Header, Type},
+    store::{Hasher, Object, ObjectFormat, ObjectStore, Oid},
+    Error, MAX_SHA_LEN,
+};
+
+mod reader;
+
+pub use reader::{resolve_pack, EntryKind, PackEntry, PackReader, Resolved};
+
+pub const OBJ_COMMIT: u8 = 1;
+pub const OBJ_TREE: u8 = 2;
+pub const OBJ_BLOB: u8 = 3;
+pub const OBJ_TAG: u8 = 4;
+pub const OBJ_OFS_DELTA: u8 = 6;
+pub const OBJ_REF_DELTA: u8 = 7;
+
+const PACK_VERSION: u32 = 2;
+const INDEX_MAGIC: &[u8] = b"\xfftOc";
+const INDEX_VERSION: u32 = 2;
+
+/// Offsets past this limit are stored in the index's 64-bit offset table
+const MAX_SMALL_OFFSET: u64 = 0x7fff_ffff;
+/// The longest delta chain followed when reading, which is the deepest git writes
+const MAX_DELTA_DEPTH: usize = 4095;
+
+pub struct IndexEntry {
+    pub oid: Oid,
+    pub crc32: u32,
+    pub offset: u64,
+}
+
+/// Writes a version 2 pack index for the given entries, returning the index checksum.
+/// Hashes in the index use the same format as the pack's checksum.
+pub fn write_index(
+    mut entries: Vec<IndexEntry>,
+    pack_checksum: &Oid,
+    output: impl Write,
+) -> crate::Result<Oid> {
+    entries.sort_unstable_by_key(|entry| entry.oid);
+
+    // everything written to the index is also hashed for its trailing checksum
+    let mut output = HashingWriter::new(output, pack_checksum.format());
+
+    output.write_all(INDEX_MAGIC)?;
+    output.write_all(&INDEX_VERSION.to_be_bytes())?;
+
+    let mut fanout = [0u32; 256];
+    for entry in &entries {
+        fanout[entry.oid.as_bytes()[0] as usize] += 1;
+    }
+    let mut total = 0;
+    for count in fanout {
+        total += count;
+        output.write_all(&total.to_be_bytes())?;
+    }
+
+    for entry in &entries {
+        output.write_all(entry.oid.as_bytes())?;
+    }
+
+    for entry in &entries {
+        output.write_all(&entry.crc32.to_be_bytes())?;
+    }
+
+    let mut large_offsets = vec![];
+    for entry in &entries {
+        let offset = if entry.offset > MAX_SMALL_OFFSET {
+            large_offsets.push(entry.offset);
+            (large_offsets.len() as u32 - 1) | 0x8000_0000
+        } else {
+            entry.offset as u32
+        };
+        output.write_all(&offset.to_be_bytes())?;
+    }
+
+    for offset in large_offsets {
+        output.write_all(&offset.to_be_bytes())?;
+    }
+
+    output.write_all(pack_checksum.as_bytes())?;
+
+    let checksum = output.hasher.finalize();
+    output.inner.write_all(checksum.as_bytes())?;
+
+    Ok(checksum)
+}
+
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Hasher,
+    written: u64,
+}
+
+impl<W> HashingWriter<W> {
+    fn new(inner: W, format: ObjectFormat) -> Self {
+        Self {
+            inner,
+            hasher: format.hasher(),
+            written: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A version 2 pack index, mapping object ids to their offsets in a pack
+pub struct Index {
+    data: Vec<u8>,
+    count: usize,
+    format: ObjectFormat,
+}
+
+impl Index {
+    pub fn open(path: impl AsRef<Path>, format: ObjectFormat) -> crate::Result<Self> {
+        let path = path.as_ref();
+        Self::parse(fs::read(path)?, format).map_err(|error| Error::in_file(path, error))
+    }
+
+    /// Parses an index whose hashes are in the given format
+    pub fn parse(data: Vec<u8>, format: ObjectFormat) -> crate::Result<Self> {
+        crate::ensure!(
+            data.len() >= 8 + 256 * 4 + 2 * format.hash_len()
+                && data.starts_with(INDEX_MAGIC)
+                && data[4..8] == INDEX_VERSION.to_be_bytes(),
+            CorruptPack,
+            "unsupported pack index format"
+        );
+        let mut index = Self {
+            data,
+            count: 0,
+            format,
+        };
+        // lookups search between neighbouring fanout entries, so they must never decrease
+        crate::ensure!(
+            (1..256).all(|byte| index.fanout(byte - 1) <= index.fanout(byte)),
+            CorruptPack,
+            "pack index fanout is not in order"
+        );
+        index.count = index.fanout(255);
+        crate::ensure!(
+            index.data.len() >= index.large_offsets() + 2 * format.hash_len(),
+            CorruptPack,
+            "pack index is truncated"
+        );
+
+        Ok(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn oid(&self, position: usize) -> Oid {
+        let len = self.format.hash_len();
+        Oid::from_bytes(&self.data[self.hashes() + position * len..][..len])
+            .expect("hash has the format's length")
+    }
+
+    pub fn crc32(&self, position: usize) -> u32 {
+        self.read_u32(self.crcs() + position * 4)
+    }
+
+    pub fn offset(&self, position: usize) -> crate::Result<u64> {
+        let offset = self.read_u32(self.offsets() + position * 4);
+        if offset & 0x8000_0000 == 0 {
+            return Ok(offset as u64);
+        }
+
+        let at = self.large_offsets() + (offset & 0x7fff_ffff) as usize * 8;
+        Ok(u64::from_be_bytes(
+            self.data
+                .get(at..at + 8)
+                .ok_or_else(|| Error::CorruptPack("pack index is truncated".into()))?
+                .try_into()
+                .expect("8 bytes"),
+        ))
+    }
+
+    /// Checksum of the pack this index belongs to
+    pub fn pack_checksum(&self) -> &[u8] {
+        let len = self.format.hash_len();
+        &self.data[self.data.len() - 2 * len..][..len]
+    }
+
+    /// Position of an object in the index, if the pack contains it
+    pub fn position(&self, oid: &Oid) -> Option<usize> {
+        let (mut start, mut end) = self.bucket(oid.as_bytes()[0]);
+        while start < end {
+            let middle = (start + end) / 2;
+            match self.oid(middle).cmp(oid) {
+                cmp::Ordering::Less => start = middle + 1,
+                cmp::Ordering::Greater => end = middle,
+                cmp::Ordering::Equal => return Some(middle),
+            }
+        }
+        None
+    }
+
+    /// Positions of every object whose hex hash starts with `prefix`
+    pub fn find(&self, prefix: &str) -> Vec<usize> {
+        let Some(first_byte) = prefix
+            .get(..2)
+            .and_then(|first_byte| u8::from_str_radix(first_byte, 16).ok())
+        else {
+            return vec![];
+        };
+
+        // hex strings sort the same way as the bytes they encode
+        let (mut position, mut end) = self.bucket(first_byte);
+        let last = end;
+        while position < end {
+            let middle = (position + end) / 2;
+            if self.oid(middle).to_string().as_str() < prefix {
+                position = middle + 1;
+            } else {
+                end = middle;
+            }
+        }
+
+        (position..last)
+            .take_while(|&position| self.oid(position).to_string().starts_with(prefix))
+            .collect()
+    }
+
+    /// Range of positions of objects whose hash starts with `first_byte`
+    fn bucket(&self, first_byte: u8) -> (usize, usize) {
+        let start = match first_byte {
+            0 => 0,
+            _ => self.fanout(first_byte as usize - 1),
+        };
+        (start, self.fanout(first_byte as usize))
+    }
+
+    fn fanout(&self, byte: usize) -> usize {
+        self.read_u32(8 + byte * 4) as usize
+    }
+
+    fn hashes(&self) -> usize {
+        8 + 256 * 4
+    }
+
+    fn crcs(&self) -> usize {
+        self.hashes() + self.count * self.format.hash_len()
+    }
+
+    fn offsets(&self) -> usize {
+        self.crcs() + self.count * 4
+    }
+
+    fn large_offsets(&self) -> usize {
+        self.offsets() + self.count * 4
+    }
+
+    fn read_u32(&self, at: usize) -> u32 {
+        u32::from_be_bytes(self.data[at..at + 4].try_into().expect("4 bytes"))
+    }
+}
+
+/// Writes a version 2 pack with every object stored whole, returning
+/// its index entries and checksum.
+pub fn write_pack(
+    objects: &[Object],
+    format: ObjectFormat,
+    compression: Compression,
+    output: impl Write,
+) -> crate::Result<(Vec<IndexEntry>, Oid)> {
+    let mut output = HashingWriter::new(output, format);
+
+    output.write_all(b"PACK")?;
+    output.write_all(&PACK_VERSION.to_be_bytes())?;
+    output.write_all(&(objects.len() as u32).to_be_bytes())?;
+
+    let mut entries = Vec::with_capacity(objects.len());
+    for object in objects {
+        let offset = output.written;
+
+        let mut entry = vec![];
+        write_entry_header(type_code(object.r#type), object.contents.len(), &mut entry);
+        ZlibEncoder::new(object.contents.as_slice(), compression).read_to_end(&mut entry)?;
+
+        let mut crc = Crc::new();
+        crc.update(&entry);
+        output.write_all(&entry)?;
+
+        entries.push(IndexEntry {
+            oid: object.oid(format),
+            crc32: crc.sum(),
+            offset,
+        });
+    }
+
+    let checksum = output.hasher.finalize();
+    output.inner.write_all(checksum.as_bytes())?;
+
+    Ok((entries, checksum))
+}
+
+/// How far `write_delta_pack` searches for delta bases
+#[derive(Clone, Copy)]
+pub struct DeltaLimits {
+    /// Number of preceding objects tried as the base of each object
+    pub window: usize,
+    /// Longest chain of deltas needed to rebuild an object
+    pub depth: usize,
+}
+
+impl Default for DeltaLimits {
+    fn default() -> Self {
+        Self {
+            window: 10,
+            depth: 50,
+        }
+    }
+}
+
+/// Writes a version 2 pack, storing objects as deltas against similar objects
+/// when that's smaller, and returns its index entries and checksum. Each object
+/// comes with the file name it was found under, since earlier versions of the
+/// same file make the best bases.
+pub fn write_delta_pack(
+    objects: &[(Object, String)],
+    limits: DeltaLimits,
+    format: ObjectFormat,
+    compression: Compression,
+    output: impl Write,
+) -> crate::Result<(Vec<IndexEntry>, Oid)> {
+    let keys = objects
+        .iter()
+        .map(|(object, name)| (object.r#type, name.as_str(), object.contents.len()))
+        .collect();
+    write_deltas(
+        keys,
+        &mut |index| Ok(objects[index].0.clone()),
+        limits,
+        format,
+        compression,
+        output,
+    )
+}
+
+/// Reads objects from `store` and writes them as a pack with deltas, like
+/// `write_delta_pack` does. Each object comes with the file name it was found
+/// under. Objects are read as they're written, so only the ones that might be
+/// delta bases are held in memory.
+pub fn pack_objects(
+    store: &dyn ObjectStore,
+    objects: &[(Oid, String)],
+    limits: DeltaLimits,
+    compression: Compression,
+    output: impl Write,
+) -> crate::Result<(Vec<IndexEntry>, Oid)> {
+    let keys = objects
+        .iter()
+        .map(|(oid, name)| {
+            let header = store
+                .read_header(oid)?
+                .ok_or_else(|| Error::ObjectNotFound(oid.to_string()))?;
+            Ok((header.r#type, name.as_str(), header.size))
+        })
+        .collect::<crate::Result<_>>()?;
+    write_deltas(
+        keys,
+        &mut |index| store.get(&objects[index].0),
+        limits,
+        store.format(),
+        compression,
+        output,
+    )
+}
+
+/// Writes the pack for `write_delta_pack` and `pack_objects`, given the type,
+/// file name and size of each object, and a way to read each one
+fn write_deltas(
+    keys: Vec<(Type, &str, usize)>,
+    read: &mut dyn FnMut(usize) -> crate::Result<Object>,
+    limits: DeltaLimits,
+    format: ObjectFormat,
+    compression: Compression,
+    output: impl Write,
+) -> crate::Result<(Vec<IndexEntry>, Oid)> {
+    // like git, put similar objects next to each other, largest first,
+    // since removing data makes for smaller deltas than adding it
+    let mut order = (0..keys.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&index| {
+        let (r#type, name, size) = keys[index];
+        (type_code(r#type), name, cmp::Reverse(size))
+    });
+
+    let mut output = HashingWriter::new(output, format);
+
+    output.write_all(b"PACK")?;
+    output.write_all(&PACK_VERSION.to_be_bytes())?;
+    output.write_all(&(keys.len() as u32).to_be_bytes())?;
+
+    let mut entries = Vec::with_capacity(keys.len());
+    // the objects that can still be bases, with their offsets and delta depths
+    let mut window: VecDeque<(Object, u64, usize)> = VecDeque::with_capacity(limits.window);
+    for index in order {
+        let object = read(index)?;
+        let offset = output.written;
+
+        // a delta has to save at least half of the object to be worth it
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        for (base, (base_object, _, base_depth)) in window.iter().enumerate() {
+            if base_object.r#type != object.r#type || *base_depth >= limits.depth {
+                continue;
+            }
+
+            let limit = best.as_ref().map_or_else(
+                || (object.contents.len() / 2).saturating_sub(format.hash_len()),
+                |(_, delta)| delta.len(),
+            );
+            if let Some(delta) = create_delta(&base_object.contents, &object.contents, limit) {
+                best = Some((base, delta));
+            }
+        }
+
+        let mut entry = vec![];
+        let depth = match best {
+            Some((base, delta)) => {
+                let (_, base_offset, base_depth) = window[base];
+                write_entry_header(OBJ_OFS_DELTA, delta.len(), &mut entry);
+                write_base_offset(offset - base_offset, &mut entry);
+                ZlibEncoder::new(delta.as_slice(), compression).read_to_end(&mut entry)?;
+                base_depth + 1
+            }
+            None => {
+                write_entry_header(type_code(object.r#type), object.contents.len(), &mut entry);
+                ZlibEncoder::new(object.contents.as_slice(), compression)
+                    .read_to_end(&mut entry)?;
+                0
+            }
+        };
+
+        let mut crc = Crc::new();
+        crc.update(&entry);
+        output.write_all(&entry)?;
+
+        entries.push(IndexEntry {
+            oid: object.oid(format),
+            crc32: crc.sum(),
+            offset,
+        });
+        if limits.window > 0 {
+            if window.len() == limits.window {
+                window.pop_front();
+            }
+            window.push_back((object, offset, depth));
+        }
+    }
+
+    let checksum = output.hasher.finalize();
+    output.inner.write_all(checksum.as_bytes())?;
+
+    Ok((entries, checksum))
+}
+
+/// Writes an entry's type and the size of its data, four bits in the first
+/// byte and seven in each of the rest
+fn write_entry_header(type_code: u8, mut size: usize, entry: &mut Vec<u8>) {
+    let mut byte = (type_code << 4) | (size as u8 & 0b0000_1111);
+    size >>= 4;
+    while size > 0 {
+        entry.push(byte | 0b1000_0000);
+        byte = size as u8 & 0b0111_1111;
+        size >>= 7;
+    }
+    entry.push(byte);
+}
+
+/// Writes how far back an OFS_DELTA's base is, most significant bits first
+fn write_base_offset(mut offset: u64, entry: &mut Vec<u8>) {
+    let mut bytes = vec![offset as u8 & 0b0111_1111];
+    offset >>= 7;
+    while offset > 0 {
+        // each continuation byte also adds one, mirroring `read_at`
+        offset -= 1;
+        bytes.push(0b1000_0000 | (offset as u8 & 0b0111_1111));
+        offset >>= 7;
+    }
+    entry.extend(bytes.iter().rev());
+}
+
+/// Reads the object at `offset` in a pack, applying any deltas.
+/// Bases of ref deltas are looked up with `find_base`, along with the depth of
+/// the chain so far, which is `depth` for the object itself.
+pub fn read_object(
+    pack: &Path,
+    offset: u64,
+    format: ObjectFormat,
+    depth: usize,
+    find_base: &dyn Fn(&Oid, usize) -> crate::Result<Object>,
+) -> crate::Result<Object> {
+    let mut file = BufReader::new(File::open(pack)?);
+    read_at(&mut file, offset, format, depth, find_base)
+}
+
+fn read_at(
+    file: &mut BufReader<File>,
+    offset: u64,
+    format: ObjectFormat,
+    depth: usize,
+    find_base: &dyn Fn(&Oid, usize) -> crate::Result<Object>,
+) -> crate::Result<Object> {
+    // ref deltas can name each other, so a chain might never reach a full object
+    crate::ensure!(
+        depth <= MAX_DELTA_DEPTH,
+        CorruptPack,
+        "delta chain is too deep"
+    );
+    let (object_type, size) = read_entry_header(file, offset)?;
+
+    match object_type {
+        OBJ_OFS_DELTA => {
+            let base_offset = read_base_offset(file, offset)?;
+            let delta = inflate(file, size)?;
+            let base = read_at(file, base_offset, format, depth + 1, find_base)?;
+
+            Ok(Object {
+                r#type: base.r#type,
+                contents: apply_delta(&base.contents, &delta)?,
+            })
+        }
+
+        OBJ_REF_DELTA => {
+            let base_ref = read_base_ref(file, format)?;
+            let delta = inflate(file, size)?;
+            let base = find_base(&base_ref, depth + 1)?;
+
+            Ok(Object {
+                r#type: base.r#type,
+                contents: apply_delta(&base.contents, &delta)?,
+            })
+        }
+
+        _ => Ok(Object {
+            r#type: object_type_from_code(object_type)?,
+            contents: inflate(file, size)?,
+        }),
+    }
+}
+
+/// Reads the type and size of the object at `offset` in a pack. Deltas only
+/// have their bases read for the type, and their size is in the delta's header.
+/// Bases of ref deltas are looked up with `find_base` like in `read_object`.
+pub fn read_header(
+    pack: &Path,
+    offset: u64,
+    format: ObjectFormat,
+    depth: usize,
+    find_base: &dyn Fn(&Oid, usize) -> crate::Result<Header>,
+) -> crate::Result<Header> {
+    let mut file = BufReader::new(File::open(pack)?);
+    read_header_at(&mut file, offset, format, depth, find_base)
+}
+
+fn read_header_at(
+    file: &mut BufReader<File>,
+    offset: u64,
+    format: ObjectFormat,
+    depth: usize,
+    find_base: &dyn Fn(&Oid, usize) -> crate::Result<Header>,
+) -> crate::Result<Header> {
+    crate::ensure!(
+        depth <= MAX_DELTA_DEPTH,
+        CorruptPack,
+        "delta chain is too deep"
+    );
+    let (object_type, size) = read_entry_header(file, offset)?;
+
+    match object_type {
+        OBJ_OFS_DELTA => {
+            let base_offset = read_base_offset(file, offset)?;
+            let size = delta_result_size(file)?;
+            let base = read_header_at(file, base_offset, format, depth + 1, find_base)?;
+
+            Ok(Header {
+                r#type: base.r#type,
+                size,
+            })
+        }
+
+        OBJ_REF_DELTA => {
+            let base_ref = read_base_ref(file, format)?;
+            let size = delta_result_size(file)?;
+            let base = find_base(&base_ref, depth + 1)?;
+
+            Ok(Header {
+                r#type: base.r#type,
+                size,
+            })
+        }
+
+        _ => Ok(Header {
+            r#type: object_type_from_code(object_type)?,
+            size: size as usize,
+        }),
+    }
+}
+
+/// Reads the type code and size at the start of the pack entry at `offset`
+fn read_entry_header(file: &mut BufReader<File>, offset: u64) -> crate::Result<(u8, u64)> {
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut byte = read_byte(file)?;
+    let object_type = byte << 1 >> 5;
+    let mut size = byte as u64 & 0b0000_1111;
+    let mut shift = 4;
+    while byte >= 128 {
+        // a malformed header could otherwise shift past the size's bits
+        crate::ensure!(shift < u64::BITS, CorruptPack, "object size is too large");
+        byte = read_byte(file)?;
+        size += (byte as u64 & 0b0111_1111) << shift;
+        shift += 7;
+    }
+
+    Ok((object_type, size))
+}
+
+/// Reads where the base of the ofs delta at `offset` starts
+fn read_base_offset(file: &mut impl Read, offset: u64) -> crate::Result<u64> {
+    // unlike the size, each continuation byte of the offset also adds one
+    let mut byte = read_byte(file)?;
+    let mut base_offset = byte as u64 & 0b0111_1111;
+    while byte >= 128 {
+        crate::ensure!(
+            base_offset < 1 << (u64::BITS - 7),
+            CorruptPack,
+            "delta offset is out of bounds"
+        );
+        byte = read_byte(file)?;
+        base_offset = ((base_offset + 1) << 7) + (byte as u64 & 0b0111_1111);
+    }
+
+    // a base must come before its delta, or the object would be its own base
+    crate::ensure!(
+        base_offset > 0 && base_offset <= offset,
+        CorruptPack,
+        "delta base offset out of bound"
+    );
+    Ok(offset - base_offset)
+}
+
+/// Reads the id of a ref delta's base
+fn read_base_ref(file: &mut impl Read, format: ObjectFormat) -> crate::Result<Oid> {
+    let mut base_ref = [0u8; MAX_SHA_LEN];
+    let base_ref = &mut base_ref[..format.hash_len()];
+    file.read_exact(base_ref)?;
+    Oid::from_bytes(base_ref)
+}
+
+/// Decompresses only as much of a delta as it takes to read the size of the
+/// object it produces
+fn delta_result_size(file: &mut impl BufRead) -> crate::Result<usize> {
+    // each size takes at most ten bytes
+    let mut header = Vec::with_capacity(20);
+    ZlibDecoder::new(file)
+        .take(20)
+        .read_to_end(&mut header)
+        .map_err(|error| Error::CorruptPack(format!("failed to decompress object: {error}")))?;
+
+    let mut delta = header.into_iter();
+    delta_size(&mut delta)?;
+    delta_size(&mut delta)
+}
+
+fn read_byte(file: &mut impl Read) -> crate::Result<u8> {
+    let mut byte = [0u8];
+    file.read_exact(&mut byte)
+        .map_err(|_| Error::CorruptPack("pack file is truncated".into()))?;
+    Ok(byte[0])
+}
+
+fn inflate(file: &mut impl BufRead, size: u64) -> crate::Result<Vec<u8>> {
+    let mut contents = Vec::with_capacity(size as usize);
+    ZlibDecoder::new(file)
+        .read_to_end(&mut contents)
+        .map_err(|error| Error::CorruptPack(format!("failed to decompress object: {error}")))?;
+    crate::ensure!(
+        contents.len() as u64 == size,
+        CorruptPack,
+        "decompressed data does not match object size"
+    );
+    Ok(contents)
+}
+
+pub fn object_type_from_code(object_type: u8) -> crate::Result<Type> {
+    Ok(match object_type {
+        OBJ_COMMIT => Type::Commit,
+        OBJ_TREE => Type::Tree,
+        OBJ_BLOB => Type::Blob,
+        OBJ_TAG => Type::Tag,
+        _ => crate::bail!(
+            CorruptPack,
+            "invalid object type {object_type} in pack file"
+        ),
+    })
+}
+
+pub fn type_code(r#type: Type) -> u8 {
+    match r#type {
+        Type::Commit => OBJ_COMMIT,
+        Type::Tree => OBJ_TREE,
+        Type::Blob => OBJ_BLOB,
+        Type::Tag => OBJ_TAG,
+    }
+}
+
+/// Encodes `target` as copy and insert instructions on `base`, or returns `None`
+/// if the delta wouldn't be smaller than `limit`.
+pub fn create_delta(base: &[u8], target: &[u8], limit: usize) -> Option<Vec<u8>> {
+    const BLOCK: usize = 16;
+    const MAX_COPY: usize = 0x10000;
+    const MAX_INSERT: usize = 0x7f;
+
+    fn size(mut size: usize, delta: &mut Vec<u8>) {
+        while size >= 128 {
+            delta.push(size as u8 | 0b1000_0000);
+            size >>= 7;
+        }
+        delta.push(size as u8);
+    }
+
+    fn insert(data: &[u8], delta: &mut Vec<u8>) {
+        for chunk in data.chunks(MAX_INSERT) {
+            delta.push(chunk.len() as u8);
+            delta.extend_from_slice(chunk);
+        }
+    }
+
+    fn copy(mut offset: usize, mut len: usize, delta: &mut Vec<u8>) {
+        while len > 0 {
+            let size = len.min(MAX_COPY);
+            let mut instruction = 0b1000_0000;
+            let at = delta.len();
+            delta.push(0);
+            for bit in 0..4 {
+                let byte = (offset >> (8 * bit)) as u8;
+                if byte != 0 {
+                    instruction |= 1 << bit;
+                    delta.push(byte);
+                }
+            }
+            // a size of 0x10000 is written as no size bytes at all
+            for bit in 0..3 {
+                let byte = ((size % MAX_COPY) >> (8 * bit)) as u8;
+                if byte != 0 {
+                    instruction |= 0b0001_0000 << bit;
+                    delta.push(byte);
+                }
+            }
+            delta[at] = instruction;
+            offset += size;
+            len -= size;
+        }
+    }
+
+    // index the start of every block in the base, keeping the first of any repeats
+    let mut blocks = HashMap::new();
+    for start in (0..base.len().saturating_sub(BLOCK - 1)).step_by(BLOCK) {
+        blocks.entry(&base[start..start + BLOCK]).or_insert(start);
+    }
+
+    let mut delta = vec![];
+    size(base.len(), &mut delta);
+    size(target.len(), &mut delta);
+
+    // start of the bytes not yet covered by an instruction
+    let mut pending = 0;
+    let mut position = 0;
+    while position + BLOCK <= target.len() {
+        let Some(&start) = blocks.get(&target[position..position + BLOCK]) else {
+            position += 1;
+            continue;
+        };
+
+        // grow the match backwards into the pending bytes, then forwards
+        let (mut base_start, mut target_start) = (start, position);
+        while base_start > 0
+            && target_start > pending
+            && base[base_start - 1] == target[target_start - 1]
+        {
+            base_start -= 1;
+            target_start -= 1;
+        }
+        let mut len = position + BLOCK - target_start;
+        while base_start + len < base.len()
+            && target_start + len < target.len()
+            && base[base_start + len] == target[target_start + len]
+        {
+            len += 1;
+        }
+
+        insert(&target[pending..target_start], &mut delta);
+        copy(base_start, len, &mut delta);
+        position = target_start + len;
+        pending = position;
+
+        if delta.len() >= limit {
+            return None;
+        }
+    }
+    insert(&target[pending..], &mut delta);
+
+    (delta.len() < limit).then_some(delta)
+}
+
+/// Reads one of the base and result sizes at the start of a delta
+fn delta_size(delta: &mut impl Iterator<Item = u8>) -> crate::Result<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        let byte = delta
+            .next()
+            .ok_or_else(|| Error::CorruptPack("delta is truncated".into()))?;
+        crate::ensure!(shift < usize::BITS, CorruptPack, "delta size is too large");
+        size |= (byte as usize & 0b0111_1111) << shift;
+        shift += 7;
+        if byte < 128 {
+            return Ok(size);
+        }
+    }
+}
+
+/// Reconstructs an object from its base and a delta's copy and insert instructions.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut delta = delta.iter().copied();
+    let base_size = delta_size(&mut delta)?;
+    let new_size = delta_size(&mut delta)?;
+    crate::ensure!(
+        base_size == base.len(),
+        CorruptPack,
+        "delta base size is incorrect"
+    );
+
+    let mut new_object = Vec::with_capacity(new_size);
+
+    while let Some(byte) = delta.next() {
+        if byte >= 128 {
+            // COPY: the low four bits select offset bytes, the next three select size bytes
+            let mut offset = 0;
+            for bit in 0..4 {
+                if byte & (1 << bit) != 0 {
+                    offset |= (delta
+                        .next()
+                        .ok_or_else(|| Error::CorruptPack("delta is truncated".into()))?
+                        as usize)
+                        << (8 * bit);
+                }
+            }
+            let mut size = 0;
+            for bit in 0..3 {
+                if byte & (0b0001_0000 << bit) != 0 {
+                    size |= (delta
+                        .next()
+                        .ok_or_else(|| Error::CorruptPack("delta is truncated".into()))?
+                        as usize)
+                        << (8 * bit);
+                }
+            }
+            let size = if size == 0 { 0x10000 } else { size };
+            let copied = base.get(offset..offset + size).ok_or_else(|| {
+                Error::CorruptPack("delta copies past the end of its base".into())
+            })?;
+            new_object.extend_from_slice(copied);
+        } else if byte > 0 {
+            // INSERT: the byte itself is the number of bytes to insert
+            let before = new_object.len();
+            new_object.extend(delta.by_ref().take(byte as usize));
+            crate::ensure!(
+                new_object.len() - before == byte as usize,
+                CorruptPack,
+                "delta is truncated"
+            );
+        } else {
+            crate::bail!(CorruptPack, "invalid delta instruction");
+        }
+    }
+
+    crate::ensure!(
+        new_object.len() == new_size,
+        CorruptPack,
+        "delta result size is incorrect"
+    );
+
+    Ok(new_object)
+}
@@ -0,0 +1,290 @@
+use std::{borrow::Cow, collections::HashMap, io};
+
+use flate2::{bufread::ZlibDecoder, Crc};
+
+use crate::{
+    parsing::Type,
+    store::{self, Object, ObjectFormat, Oid},
+    Error,
+};
+
+use super::{apply_delta, object_type_from_code, OBJ_OFS_DELTA, OBJ_REF_DELTA};
+
+/// What an entry in a pack holds
+#[derive(Clone, Copy)]
+pub enum EntryKind {
+    Whole(Type),
+    /// A delta whose base is the entry at this offset
+    OfsDelta(u64),
+    /// A delta whose base is the object with this id
+    RefDelta(Oid),
+}
+
+/// An entry read from a pack, before any delta is applied
+pub struct PackEntry {
+    pub offset: u64,
+    pub kind: EntryKind,
+    /// The decompressed object or delta
+    pub data: Vec<u8>,
+    /// Bytes taken up in the pack, including the entry's header
+    pub packed_size: u64,
+    pub crc32: u32,
+}
+
+/// Reads the entries of a pack held in memory, in the order they're stored
+pub struct PackReader<'a> {
+    contents: &'a [u8],
+    format: ObjectFormat,
+    checksum: Oid,
+    count: usize,
+    read: usize,
+    position: usize,
+}
+
+impl<'a> PackReader<'a> {
+    /// Checks the pack's header and trailing checksum, whose hash is in the given format
+    pub fn new(pack: &'a [u8], format: ObjectFormat) -> crate::Result<Self> {
+        let hash_len = format.hash_len();
+        crate::ensure!(
+            pack.len() >= 12 + hash_len && pack.starts_with(b"PACK"),
+            CorruptPack,
+            "invalid pack file header"
+        );
+        let (contents, checksum) = pack.split_at(pack.len() - hash_len);
+        let mut hasher = format.hasher();
+        hasher.update(contents);
+        crate::ensure!(
+            hasher.finalize().as_bytes() == checksum,
+            CorruptPack,
+            "pack file checksum does not match"
+        );
+        let version = u32::from_be_bytes(pack[4..8].try_into().expect("4 bytes"));
+        crate::ensure!(
+            matches!(version, 2 | 3),
+            CorruptPack,
+            "unsupported pack version {version}"
+        );
+
+        Ok(Self {
+            contents,
+            format,
+            checksum: Oid::from_bytes(checksum)?,
+            count: u32::from_be_bytes(pack[8..12].try_into().expect("4 bytes")) as usize,
+            read: 0,
+            position: 12,
+        })
+    }
+
+    /// Number of entries in the pack
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn format(&self) -> ObjectFormat {
+        self.format
+    }
+
+    pub fn checksum(&self) -> Oid {
+        self.checksum
+    }
+
+    fn read_entry(&mut self) -> crate::Result<PackEntry> {
+        let pack = self.contents;
+        let hash_len = self.format.hash_len();
+        let mut index = self.position;
+
+        crate::ensure!(index < pack.len(), CorruptPack, "pack file is truncated");
+        let offset = index;
+        let object_type = pack[index] << 1 >> 5;
+        let mut size = pack[index] as u64 & 0b0000_1111;
+        let mut shift = 4;
+        while pack[index] >= 128 {
+            // a malformed header could otherwise shift past the size's bits
+            crate::ensure!(shift < u64::BITS, CorruptPack, "object size is too large");
+            index += 1;
+            crate::ensure!(index < pack.len(), CorruptPack, "pack file is truncated");
+            size += (pack[index] as u64 & 0b0111_1111) << shift;
+            shift += 7;
+        }
+        index += 1;
+
+        let kind = if object_type == OBJ_OFS_DELTA {
+            // unlike the size, each continuation byte of the offset also adds one
+            crate::ensure!(index < pack.len(), CorruptPack, "pack file is truncated");
+            let mut base_offset = pack[index] as usize & 0b0111_1111;
+            while pack[index] >= 128 {
+                crate::ensure!(
+                    base_offset < 1 << (usize::BITS - 7),
+                    CorruptPack,
+                    "delta offset is out of bounds"
+                );
+                index += 1;
+                crate::ensure!(index < pack.len(), CorruptPack, "pack file is truncated");
+                base_offset = ((base_offset + 1) << 7) + (pack[index] as usize & 0b0111_1111);
+            }
+            index += 1;
+            EntryKind::OfsDelta(
+                offset
+                    .checked_sub(base_offset)
+                    .ok_or_else(|| Error::CorruptPack("delta offset is out of bounds".into()))?
+                    as u64,
+            )
+        } else if object_type == OBJ_REF_DELTA {
+            let base_ref = pack
+                .get(index..index + hash_len)
+                .ok_or_else(|| Error::CorruptPack("pack file is truncated".into()))?;
+            index += hash_len;
+            EntryKind::RefDelta(Oid::from_bytes(base_ref)?)
+        } else {
+            EntryKind::Whole(object_type_from_code(object_type)?)
+        };
+
+        let mut decompressor = ZlibDecoder::new(pack.get(index..).unwrap_or_default());
+        let mut data = vec![];
+        io::copy(&mut decompressor, &mut data)
+            .map_err(|error| Error::CorruptPack(format!("failed to decompress object: {error}")))?;
+        crate::ensure!(
+            size == decompressor.total_out(),
+            CorruptPack,
+            "decompressed data does not match object size"
+        );
+        index += decompressor.total_in() as usize;
+
+        let mut crc = Crc::new();
+        crc.update(&pack[offset..index]);
+
+        self.position = index;
+        self.read += 1;
+        if self.read == self.count {
+            crate::ensure!(
+                index == pack.len(),
+                CorruptPack,
+                "pack file has trailing data after {} objects",
+                self.count
+            );
+        }
+
+        Ok(PackEntry {
+            offset: offset as u64,
+            kind,
+            data,
+            packed_size: (index - offset) as u64,
+            crc32: crc.sum(),
+        })
+    }
+}
+
+impl Iterator for PackReader<'_> {
+    type Item = crate::Result<PackEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.read == self.count {
+            return None;
+        }
+
+        let entry = self.read_entry();
+        if entry.is_err() {
+            // nothing after a bad entry can be found
+            self.read = self.count;
+        }
+        Some(entry)
+    }
+}
+
+/// An object from a pack, after applying any deltas
+pub struct Resolved<'a> {
+    pub oid: Oid,
+    pub r#type: Type,
+    pub contents: &'a [u8],
+    pub entry: &'a PackEntry,
+    /// Number of deltas applied to reach the object
+    pub depth: usize,
+    /// The object this one is a delta of
+    pub base: Option<Oid>,
+}
+
+/// Reads every entry of a pack and applies deltas, passing each object to `visit`.
+/// Bases of ref deltas that aren't in the pack are looked up with `find_base`,
+/// which lets thin packs be resolved against objects that are already stored.
+pub fn resolve_pack(
+    reader: PackReader,
+    find_base: &dyn Fn(&Oid) -> crate::Result<Option<Object>>,
+    mut visit: impl FnMut(Resolved) -> crate::Result<()>,
+) -> crate::Result<()> {
+    let format = reader.format();
+    let entries = reader.collect::<crate::Result<Vec<_>>>()?;
+
+    let mut by_offset = HashMap::<_, Vec<_>>::new();
+    let mut by_ref = HashMap::<_, Vec<_>>::new();
+    // entries to resolve, with their type, contents, depth and base
+    let mut stack = vec![];
+    for (position, entry) in entries.iter().enumerate().rev() {
+        match entry.kind {
+            EntryKind::Whole(r#type) => stack.push((position, r#type, None, 0, None)),
+            EntryKind::OfsDelta(base_offset) => {
+                by_offset.entry(base_offset).or_default().push(position)
+            }
+            EntryKind::RefDelta(base) => by_ref.entry(base).or_default().push(position),
+        }
+    }
+
+    // resolve deltas starting from the base objects, since a base may
+    // appear anywhere in the pack and may itself be a delta
+    loop {
+        while let Some((position, r#type, contents, depth, base)) = stack.pop() {
+            let entry = &entries[position];
+            let contents = match contents {
+                Some(contents) => Cow::Owned(contents),
+                None => Cow::Borrowed(entry.data.as_slice()),
+            };
+            let oid = store::hash(format, r#type, &contents);
+
+            let children = by_offset
+                .remove(&entry.offset)
+                .into_iter()
+                .chain(by_ref.remove(&oid))
+                .flatten();
+            for child in children {
+                let resolved = apply_delta(&contents, &entries[child].data)?;
+                stack.push((child, r#type, Some(resolved), depth + 1, Some(oid)));
+            }
+
+            visit(Resolved {
+                oid,
+                r#type,
+                contents: &contents,
+                entry,
+                depth,
+                base,
+            })?;
+        }
+
+        // what's left are ref deltas whose bases are outside the pack
+        let mut found = false;
+        for base in by_ref.keys().copied().collect::<Vec<_>>() {
+            if let Some(object) = find_base(&base)? {
+                for child in by_ref.remove(&base).expect("key was just listed") {
+                    let resolved = apply_delta(&object.contents, &entries[child].data)?;
+                    stack.push((child, object.r#type, Some(resolved), 1, Some(base)));
+                }
+                found = true;
+            }
+        }
+        if !found {
+            break;
+        }
+    }
+
+    let unresolved = by_offset.values().chain(by_ref.values()).flatten().count();
+    crate::ensure!(
+        unresolved == 0,
+        CorruptPack,
+        "failed to resolve {unresolved} deltas"
+    );
+
+    Ok(())
+}
@@ -7,13 +7,18 @@ use nom::{
         complete::{char, digit1, newline, one_of},
         is_digit, is_hex_digit,
     },
+    combinator::{opt, recognize},
     multi::many0,
-    sequence::separated_pair,
+    sequence::{separated_pair, tuple},
     IResult,
 };
 
-use crate::{utils, SHA_DISPLAY_LEN, SHA_LEN};
+use crate::{
+    store::{ObjectFormat, Oid},
+    utils, Error,
+};
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Type {
     Blob,
     Tree,
@@ -49,39 +54,70 @@ pub struct Header {
     pub size: usize,
 }
 
+#[derive(Clone)]
 pub struct Commit {
     pub hash: Option<String>,
-    pub parents: Vec<[u8; SHA_DISPLAY_LEN]>,
+    pub tree: Oid,
+    pub parents: Vec<Oid>,
     pub author: String,
     pub timestamp: u32,
     pub timezone: [u8; 5],
+    /// When the commit was made, which history is ordered by
+    pub commit_time: u32,
     pub message: String,
 }
 
-pub struct Error(anyhow::Error);
-
-impl fmt::Debug for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
-    }
+/// An annotated tag
+pub struct Tag {
+    pub object: Oid,
+    pub r#type: Type,
+    pub name: String,
+    pub tagger: Option<Tagger>,
+    pub message: String,
+    pub signature: Option<String>,
 }
 
-impl Error {
-    fn new(msg: &str) -> nom::Err<Self> {
-        nom::Err::Error(Self(anyhow::anyhow!("{msg}")))
-    }
+pub struct Tagger {
+    pub name: String,
+    pub timestamp: u32,
+    pub timezone: [u8; 5],
 }
 
-impl<I> nom::error::ParseError<I> for Error {
-    fn from_error_kind(_input: I, kind: nom::error::ErrorKind) -> Self {
-        Self(anyhow::anyhow!("{}", kind.description()))
-    }
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "object {}", self.object)?;
+        writeln!(f, "type {}", self.r#type)?;
+        writeln!(f, "tag {}", self.name)?;
+        if let Some(Tagger {
+            name,
+            timestamp,
+            timezone,
+        }) = &self.tagger
+        {
+            let timezone = String::from_utf8_lossy(timezone);
+            writeln!(f, "tagger {name} {timestamp} {timezone}")?;
+        }
 
-    fn append(_input: I, kind: nom::error::ErrorKind, other: Self) -> Self {
-        Self(anyhow::anyhow!("{}\n{}", kind.description(), other.0))
+        if !self.message.is_empty() || self.signature.is_some() {
+            writeln!(f)?;
+            write!(f, "{}", self.message)?;
+            if let Some(signature) = &self.signature {
+                write!(f, "{signature}")?;
+            }
+        }
+
+        Ok(())
     }
 }
 
+/// Parsers fail with a reason, which callers that know the object's id add it to
+fn error(reason: &str) -> nom::Err<Error> {
+    nom::Err::Error(Error::CorruptObject {
+        oid: None,
+        reason: reason.into(),
+    })
+}
+
 /// Object type
 pub fn parse_type(object: &[u8]) -> IResult<&[u8], Type, Error> {
     let mut object_type = alt((tag("blob"), tag("tree"), tag("commit"), tag("tag")));
@@ -96,9 +132,9 @@ fn parse_size(object: &[u8]) -> IResult<&[u8], usize, Error> {
     let (object, size) = digit1(object)?;
 
     let size = std::str::from_utf8(size)
-        .map_err(|_| Error::new("invalid size in object file"))?
+        .map_err(|_| error("invalid size in object file"))?
         .parse::<usize>()
-        .map_err(|_| Error::new("failed to parse size"))?;
+        .map_err(|_| error("failed to parse size"))?;
 
     Ok((object, size))
 }
@@ -116,51 +152,27 @@ pub fn parse_contents(object: &[u8]) -> IResult<&[u8], Type, Error> {
     let (object, Header { r#type, size }) = parse_header(object)?;
 
     if object.len() != size {
-        return Err(Error::new("object size is incorrect"));
+        return Err(error("object size is incorrect"));
     }
 
     Ok((object, r#type))
 }
 
-/// Tree entries
-pub fn parse_tree(recurse: bool) -> impl Fn(&[u8]) -> IResult<&[u8], Vec<utils::Entry>, Error> {
-    move |object| {
-        let (object, Header { r#type, size }) = parse_header(object)?;
-
-        if !matches!(r#type, Type::Tree) {
-            return Err(Error::new("object is not a tree"));
-        }
-        if object.len() != size {
-            return Err(Error::new("object size is incorrect"));
-        }
-
-        many0(entry(recurse))(object)
-    }
+/// Tree entries, whose hashes are in the given format
+pub fn parse_tree(
+    format: ObjectFormat,
+) -> impl Fn(&[u8]) -> IResult<&[u8], Vec<utils::Entry>, Error> {
+    move |contents| many0(entry(format))(contents)
 }
 
-fn entry(recurse: bool) -> impl Fn(&[u8]) -> IResult<&[u8], utils::Entry, Error> {
+fn entry(format: ObjectFormat) -> impl Fn(&[u8]) -> IResult<&[u8], utils::Entry, Error> {
     move |object| {
         let (object, mode) = mode(object)?;
         let (object, _) = char(' ')(object)?;
         let (object, name) = name(object)?;
         let (object, _) = char('\0')(object)?;
-        let (object, hash) = hash(object)?;
+        let (object, hash) = hash(format)(object)?;
         let tree = mode == 40_000;
-        let children = if tree && recurse {
-            let hash = {
-                use std::fmt::Write;
-
-                let mut new_hash = String::with_capacity(SHA_DISPLAY_LEN);
-                for byte in hash {
-                    write!(new_hash, "{byte:02x}").expect("writing to a string");
-                }
-                new_hash
-            };
-
-            Some(utils::tree_level(&hash, true).map_err(|error| nom::Err::Error(Error(error)))?)
-        } else {
-            None
-        };
 
         Ok((
             object,
@@ -169,7 +181,7 @@ fn entry(recurse: bool) -> impl Fn(&[u8]) -> IResult<&[u8], utils::Entry, Error>
                 hash,
                 name,
                 tree,
-                children,
+                children: None,
                 display: Default::default(),
             },
         ))
@@ -194,58 +206,69 @@ fn name(object: &[u8]) -> IResult<&[u8], String, Error> {
     Ok((object, String::from_utf8_lossy(name).into_owned()))
 }
 
-fn hash(object: &[u8]) -> IResult<&[u8], [u8; SHA_LEN], Error> {
-    let hash = object
-        .get(..SHA_LEN)
-        .ok_or_else(|| Error::new("failed to read hash"))?
-        .try_into()
-        .expect("got 20 bytes");
+fn hash(format: ObjectFormat) -> impl Fn(&[u8]) -> IResult<&[u8], Oid, Error> {
+    move |object| {
+        let hash = object
+            .get(..format.hash_len())
+            .ok_or_else(|| error("failed to read hash"))?;
 
-    Ok((&object[SHA_LEN..], hash))
+        Ok((
+            &object[format.hash_len()..],
+            Oid::from_bytes(hash).expect("hash has the format's length"),
+        ))
+    }
 }
 
 pub fn parse_commit(contents: &[u8]) -> IResult<&[u8], Commit, Error> {
-    let (contents, _) = tree(contents)?;
+    let (contents, tree) = tree(contents)?;
     let (contents, parents) = many0(parent)(contents)?;
-    let (contents, author) = author(contents)?;
+    let (contents, author) = ident("author ")(contents)?;
     let (contents, timestamp) = timestamp(contents)?;
     let (contents, timezone) = timezone(contents)?;
-    let (contents, _) = committer(contents)?;
+    let (contents, commit_time) = committer(contents)?;
     let (contents, message) = message(contents)?;
 
     Ok((
         contents,
         Commit {
             hash: None,
+            tree,
             parents,
             author,
             timestamp,
             timezone,
+            commit_time,
             message,
         },
     ))
 }
 
-fn hex_hash(contents: &[u8]) -> IResult<&[u8], [u8; SHA_DISPLAY_LEN], Error> {
-    let (contents, hash) =
-        take_while_m_n(SHA_DISPLAY_LEN, SHA_DISPLAY_LEN, is_hex_digit)(contents)?;
+/// A hash in either format, written as hex digits
+fn hex_hash(contents: &[u8]) -> IResult<&[u8], Oid, Error> {
+    let (contents, hash) = take_while_m_n(
+        ObjectFormat::Sha1.hex_len(),
+        ObjectFormat::Sha256.hex_len(),
+        is_hex_digit,
+    )(contents)?;
 
     Ok((
         contents,
-        hash.try_into()
-            .expect("must have taken SHA_DISPLAY_LEN bytes"),
+        std::str::from_utf8(hash)
+            .expect("hex digits")
+            .parse()
+            .map_err(|_| error("invalid hash length"))?,
     ))
 }
 
-fn tree(contents: &[u8]) -> IResult<&[u8], (), Error> {
+fn tree(contents: &[u8]) -> IResult<&[u8], Oid, Error> {
     let (contents, _) = tag("tree ")(contents)?;
-    let (contents, _) = hex_hash(contents)?;
+    let (contents, hash) = hex_hash(contents)?;
     let (contents, _) = newline(contents)?;
 
-    Ok((contents, ()))
+    Ok((contents, hash))
 }
 
-fn parent(contents: &[u8]) -> IResult<&[u8], [u8; SHA_DISPLAY_LEN], Error> {
+fn parent(contents: &[u8]) -> IResult<&[u8], Oid, Error> {
     let (contents, _) = tag("parent ")(contents)?;
     let (contents, hash) = hex_hash(contents)?;
     let (contents, _) = newline(contents)?;
@@ -253,30 +276,43 @@ fn parent(contents: &[u8]) -> IResult<&[u8], [u8; SHA_DISPLAY_LEN], Error> {
     Ok((contents, hash))
 }
 
-fn author(contents: &[u8]) -> IResult<&[u8], String, Error> {
-    let (contents, _) = tag(b"author ")(contents)?;
-    let (contents, name) = take_until1(" <")(contents)?;
-    let (contents, _) = tag(b" <")(contents)?;
-    let (contents, email) = take_until1("> ")(contents)?;
-    let (contents, _) = tag(b"> ")(contents)?;
+/// Name and email following `keyword`, like the author of a commit
+fn ident(keyword: &'static str) -> impl Fn(&[u8]) -> IResult<&[u8], String, Error> {
+    move |contents| {
+        let (contents, _) = tag(keyword)(contents)?;
+        let (contents, name) = take_until1(" <")(contents)?;
+        let (contents, _) = tag(b" <")(contents)?;
+        let (contents, email) = take_until1("> ")(contents)?;
+        let (contents, _) = tag(b"> ")(contents)?;
 
-    Ok((
-        contents,
-        format!(
-            "{} <{}>",
-            std::str::from_utf8(name).map_err(|_| Error::new("failed to parse name"))?,
-            std::str::from_utf8(email).map_err(|_| Error::new("failed to parse email"))?,
-        ),
-    ))
+        Ok((
+            contents,
+            format!(
+                "{} <{}>",
+                std::str::from_utf8(name).map_err(|_| error("failed to parse name"))?,
+                std::str::from_utf8(email).map_err(|_| error("failed to parse email"))?,
+            ),
+        ))
+    }
 }
 
-fn committer(contents: &[u8]) -> IResult<&[u8], (), Error> {
+/// Headers after the committer, like encoding or gpgsig, which are skipped
+fn extra_header(contents: &[u8]) -> IResult<&[u8], (), Error> {
     let (contents, _) = take_until1("\n")(contents)?;
     let (contents, _) = newline(contents)?;
 
     Ok((contents, ()))
 }
 
+/// The committer line, of which only the time is kept
+fn committer(contents: &[u8]) -> IResult<&[u8], u32, Error> {
+    let (contents, _) = ident("committer ")(contents)?;
+    let (contents, commit_time) = timestamp(contents)?;
+    let (contents, _) = timezone(contents)?;
+
+    Ok((contents, commit_time))
+}
+
 fn timestamp(contents: &[u8]) -> IResult<&[u8], u32, Error> {
     let (contents, digits) = digit1(contents)?;
     let (contents, _) = char(' ')(contents)?;
@@ -286,7 +322,7 @@ fn timestamp(contents: &[u8]) -> IResult<&[u8], u32, Error> {
         std::str::from_utf8(digits)
             .ok()
             .and_then(|digits| digits.parse().ok())
-            .ok_or_else(|| Error::new("failed to parse timestamp"))?,
+            .ok_or_else(|| error("failed to parse timestamp"))?,
     ))
 }
 
@@ -303,26 +339,109 @@ fn timezone(contents: &[u8]) -> IResult<&[u8], [u8; 5], Error> {
 }
 
 fn message(contents: &[u8]) -> IResult<&[u8], String, Error> {
+    let (contents, _) = many0(extra_header)(contents)?;
     let (contents, _) = newline(contents)?;
 
     Ok((b"", String::from_utf8_lossy(contents).into()))
 }
 
+pub fn parse_tag(contents: &[u8]) -> IResult<&[u8], Tag, Error> {
+    let (contents, _) = tag("object ")(contents)?;
+    let (contents, object) = hex_hash(contents)?;
+    let (contents, _) = newline(contents)?;
+    let (contents, _) = tag("type ")(contents)?;
+    let (contents, r#type) = parse_type(contents)?;
+    let (contents, _) = newline(contents)?;
+    let (contents, _) = tag("tag ")(contents)?;
+    let (contents, name) = take_until1("\n")(contents)?;
+    let (contents, _) = newline(contents)?;
+    let (contents, tagger) = opt(tagger)(contents)?;
+
+    // the message is separated from the headers by a blank line, if there is one
+    let (message, signature) = match contents {
+        [] => (String::new(), None),
+        [b'\n', body @ ..] => split_signature(&String::from_utf8_lossy(body)),
+        _ => return Err(error("extra header(s) after 'tagger'")),
+    };
+
+    Ok((
+        b"",
+        Tag {
+            object,
+            r#type,
+            name: String::from_utf8_lossy(name).into_owned(),
+            tagger,
+            message,
+            signature,
+        },
+    ))
+}
+
+fn tagger(contents: &[u8]) -> IResult<&[u8], Tagger, Error> {
+    let (contents, name) = ident("tagger ")(contents)?;
+    let (contents, timestamp) = timestamp(contents)?;
+    let (contents, timezone) = timezone(contents)?;
+
+    Ok((
+        contents,
+        Tagger {
+            name,
+            timestamp,
+            timezone,
+        },
+    ))
+}
+
+/// Splits a tag's message from the signature appended to it
+fn split_signature(body: &str) -> (String, Option<String>) {
+    const SIGNATURES: &[&str] = &[
+        "-----BEGIN PGP SIGNATURE-----",
+        "-----BEGIN PGP MESSAGE-----",
+        "-----BEGIN SSH SIGNATURE-----",
+        "-----BEGIN SIGNED MESSAGE-----",
+    ];
+
+    let mut start = 0;
+    for line in body.split_inclusive('\n') {
+        if SIGNATURES
+            .iter()
+            .any(|signature| line.starts_with(signature))
+        {
+            return (body[..start].into(), Some(body[start..].into()));
+        }
+        start += line.len();
+    }
+
+    (body.into(), None)
+}
+
+pub type RefRecord<'a> = (Oid, &'a str);
+
+/// Refs along with the server's capabilities
+pub type Advertisement<'a> = (Vec<RefRecord<'a>>, Vec<&'a str>);
+
 pub fn advertisement_response<'a>(
     service: &'a str,
-) -> impl Fn(&'a [u8]) -> IResult<&[u8], Vec<([u8; SHA_DISPLAY_LEN], &str)>, Error> {
+) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], Advertisement<'a>, Error> {
     move |contents| {
         let (contents, _) = pkt_line(contents)?;
         let (contents, _) = tag("# service=")(contents)?;
         let (contents, _) = tag(service)(contents)?;
         let (contents, _) = newline(contents)?;
         let (contents, _) = tag("0000")(contents)?;
-        let (contents, _) = take_until1("\n")(contents)?;
+        let (contents, _) = take_until1("\0")(contents)?;
+        let (contents, _) = char('\0')(contents)?;
+        let (contents, capabilities) = take_until1("\n")(contents)?;
         let (contents, _) = newline(contents)?;
         let (contents, refs) = many0(ref_record)(contents)?;
         let (contents, _) = tag("0000")(contents)?;
 
-        Ok((contents, refs))
+        let capabilities = std::str::from_utf8(capabilities)
+            .map_err(|_| error("capabilities are not UTF-8"))?
+            .split(' ')
+            .collect();
+
+        Ok((contents, (refs, capabilities)))
     }
 }
 
@@ -330,7 +449,7 @@ fn pkt_line(contents: &[u8]) -> IResult<&[u8], &[u8], Error> {
     take_while_m_n(4, 4, is_hex_digit)(contents)
 }
 
-fn ref_record(contents: &[u8]) -> IResult<&[u8], ([u8; SHA_DISPLAY_LEN], &str), Error> {
+fn ref_record(contents: &[u8]) -> IResult<&[u8], RefRecord<'_>, Error> {
     let (contents, _) = pkt_line(contents)?;
     let (contents, hash) = hex_hash(contents)?;
     let (contents, _) = char(' ')(contents)?;
@@ -342,11 +461,16 @@ fn ref_record(contents: &[u8]) -> IResult<&[u8], ([u8; SHA_DISPLAY_LEN], &str),
         contents,
         (
             hash,
-            std::str::from_utf8(name).map_err(|_| Error::new("ref name is not UTF-8"))?,
+            std::str::from_utf8(name).map_err(|_| error("ref name is not UTF-8"))?,
         ),
     ))
 }
 
+/// Skips the server's reply to the haves before the pack: NAK if it has none
+/// of them, otherwise ACK and the first one it has
 pub fn pack_file_response(contents: &[u8]) -> IResult<&[u8], &[u8], Error> {
-    tag("0008NAK\n")(contents)
+    alt((
+        tag("0008NAK\n"),
+        recognize(tuple((pkt_line, tag("ACK "), hex_hash, newline))),
+    ))(contents)
 }
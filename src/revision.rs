@@ -0,0 +1,264 @@
+//! Revision names, like `main~2`, `v1.0^{tree}`, or `HEAD:src/main.rs`
+
+use std::{fs, path::Path};
+
+use crate::{
+    parsing::{self, Type},
+    store::{ObjectStore, Oid},
+    utils, Error, DOT_GIT, HEAD, LOGS,
+};
+
+/// Finds the object a revision names. A revision is a full or abbreviated hash,
+/// a ref, `@` for HEAD, or `@{-N}` for the branch checked out N switches ago,
+/// followed by any number of `~N`, `^N` and `^{type}` suffixes, and optionally
+/// by `:path` to name an entry of its tree.
+pub fn resolve(store: &dyn ObjectStore, revision: &str) -> crate::Result<Oid> {
+    if let Some((revision, path)) = revision.split_once(':') {
+        if revision.is_empty() {
+            return Err(Error::invalid_ref(
+                format!(":{path}"),
+                "there is no index to look up paths in",
+            ));
+        }
+        let mut oid = peel(store, resolve(store, revision)?, Some(Type::Tree), revision)?;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let object = store.get(&oid)?;
+            let entries = match object.r#type {
+                Type::Tree => {
+                    parsing::parse_tree(store.format())(&object.contents)
+                        .map_err(|error| Error::corrupt(oid, error))?
+                        .1
+                }
+                _ => vec![],
+            };
+            oid = entries
+                .into_iter()
+                .find_map(|entry| (entry.name == name).then_some(entry.hash))
+                .ok_or_else(|| {
+                    Error::invalid_ref(
+                        format!("{revision}:{path}"),
+                        format!("path does not exist in '{revision}'"),
+                    )
+                })?;
+        }
+        return Ok(oid);
+    }
+
+    // ref names can't contain '~' or '^', so the suffixes start at the first one
+    let end = revision.find(['~', '^']).unwrap_or(revision.len());
+    let (name, mut suffixes) = revision.split_at(end);
+    let mut oid = resolve_name(store, name)?;
+
+    while !suffixes.is_empty() {
+        let operator = suffixes.as_bytes()[0];
+        suffixes = &suffixes[1..];
+
+        if operator == b'^' && suffixes.starts_with('{') {
+            let (r#type, rest) = suffixes[1..]
+                .split_once('}')
+                .ok_or_else(|| Error::invalid_ref(revision, "invalid revision"))?;
+            suffixes = rest;
+            oid = match r#type {
+                "" => peel(store, oid, None, revision)?,
+                "object" => oid,
+                "commit" => peel(store, oid, Some(Type::Commit), revision)?,
+                "tree" => peel(store, oid, Some(Type::Tree), revision)?,
+                "blob" => peel(store, oid, Some(Type::Blob), revision)?,
+                "tag" => peel(store, oid, Some(Type::Tag), revision)?,
+                _ => {
+                    return Err(Error::invalid_ref(
+                        revision,
+                        format!("invalid object type '{type}'"),
+                    ))
+                }
+            };
+            continue;
+        }
+
+        let digits = suffixes
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(suffixes.len());
+        let number = match &suffixes[..digits] {
+            "" => 1,
+            number => number
+                .parse::<usize>()
+                .map_err(|_| Error::invalid_ref(revision, "invalid revision"))?,
+        };
+        suffixes = &suffixes[digits..];
+
+        let commit = peel(store, oid, Some(Type::Commit), revision)?;
+        oid = if operator == b'~' {
+            let mut oid = commit;
+            for _ in 0..number {
+                oid = *parents(store, &oid)?
+                    .first()
+                    .ok_or_else(|| Error::invalid_ref(revision, "unknown revision"))?;
+            }
+            oid
+        } else if number == 0 {
+            commit
+        } else {
+            *parents(store, &commit)?
+                .get(number - 1)
+                .ok_or_else(|| Error::invalid_ref(revision, "unknown revision"))?
+        };
+    }
+
+    Ok(oid)
+}
+
+/// Finds the object a revision names, then peels it to an object of the given
+/// type, like `<revision>^{type}` does
+pub fn resolve_as(store: &dyn ObjectStore, revision: &str, r#type: Type) -> crate::Result<Oid> {
+    peel(store, resolve(store, revision)?, Some(r#type), revision)
+}
+
+/// Splits a range `<from>..<to>` into its ends, where either defaults to HEAD.
+/// Returns `None` for a single revision, including one whose path has `..` in it
+/// like `HEAD:../file`.
+pub fn split_range(revision: &str) -> crate::Result<Option<(&str, &str)>> {
+    let name = revision.split_once(':').map_or(revision, |(name, _)| name);
+    if name.contains("...") {
+        return Err(Error::invalid_ref(
+            revision,
+            "symmetric differences aren't supported",
+        ));
+    }
+
+    Ok(name.find("..").map(|at| {
+        // the end of the range may have a path after it, which belongs to `to`
+        let (from, to) = (&revision[..at], &revision[at + 2..]);
+        (
+            if from.is_empty() { HEAD } else { from },
+            if to.is_empty() { HEAD } else { to },
+        )
+    }))
+}
+
+/// Resolves a revision without suffixes: a hash, a ref, `@` or `@{-N}`
+fn resolve_name(store: &dyn ObjectStore, name: &str) -> crate::Result<Oid> {
+    let is_hex = name.bytes().all(|byte| byte.is_ascii_hexdigit());
+    if !name.is_empty() && is_hex && name.len() == store.format().hex_len() {
+        return name.parse();
+    }
+
+    if name == "@" {
+        return utils::resolve_head()?
+            .ok_or_else(|| Error::invalid_ref(HEAD, "does not point to a commit yet"));
+    }
+    if let Some(previous) = name
+        .strip_prefix("@{-")
+        .and_then(|name| name.strip_suffix('}'))
+    {
+        let previous = previous
+            .parse::<usize>()
+            .ok()
+            .filter(|&previous| previous > 0)
+            .ok_or_else(|| Error::invalid_ref(name, "invalid revision"))?;
+        let branch = previous_branch(previous)?.ok_or_else(|| {
+            Error::invalid_ref(name, "not enough branch switches in the HEAD reflog")
+        })?;
+        return match utils::read_ref(&format!("refs/heads/{branch}"))? {
+            Some(oid) => Ok(oid),
+            // a detached HEAD is recorded by its hash
+            None => resolve_name(store, &branch),
+        };
+    }
+
+    if name.is_empty() {
+        return Err(Error::invalid_ref(name, "missing revision name"));
+    }
+
+    // the same order git uses to pick between refs with the same short name
+    let pseudo_ref = name
+        .bytes()
+        .all(|byte| byte.is_ascii_uppercase() || byte == b'_');
+    if pseudo_ref || name.starts_with("refs/") {
+        if let Some(oid) = utils::read_ref(name)? {
+            return Ok(oid);
+        }
+    }
+    for full_name in [
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ] {
+        if let Some(oid) = utils::read_ref(&full_name)? {
+            return Ok(oid);
+        }
+    }
+
+    if !is_hex {
+        return Err(Error::invalid_ref(name, "unknown revision"));
+    }
+    store.resolve(name)
+}
+
+/// The branch checked out before the `previous`th most recent switch, from the HEAD reflog
+fn previous_branch(previous: usize) -> crate::Result<Option<String>> {
+    let Ok(log) = fs::read_to_string(Path::new(DOT_GIT).join(LOGS).join(HEAD)) else {
+        return Ok(None);
+    };
+
+    Ok(log
+        .lines()
+        .rev()
+        .filter_map(|line| {
+            let (_, message) = line.split_once('\t')?;
+            let (from, _) = message
+                .strip_prefix("checkout: moving from ")?
+                .split_once(" to ")?;
+            Some(from.to_owned())
+        })
+        .nth(previous - 1))
+}
+
+/// Follows tags, and commits to their trees, until reaching an object of the given
+/// type. Without a type, only tags are followed.
+fn peel(
+    store: &dyn ObjectStore,
+    mut oid: Oid,
+    r#type: Option<Type>,
+    revision: &str,
+) -> crate::Result<Oid> {
+    loop {
+        let object = store
+            .read(&oid)?
+            .ok_or_else(|| Error::invalid_ref(revision, "unknown revision"))?;
+        if Some(object.r#type) == r#type {
+            return Ok(oid);
+        }
+
+        oid = match (object.r#type, r#type) {
+            (Type::Tag, _) => {
+                parsing::parse_tag(&object.contents)
+                    .map_err(|error| Error::corrupt(oid, error))?
+                    .1
+                    .object
+            }
+            (_, None) => return Ok(oid),
+            (Type::Commit, Some(Type::Tree)) => {
+                parsing::parse_commit(&object.contents)
+                    .map_err(|error| Error::corrupt(oid, error))?
+                    .1
+                    .tree
+            }
+            (found, Some(expected)) => {
+                return Err(Error::WrongType {
+                    oid,
+                    expected,
+                    found,
+                })
+            }
+        };
+    }
+}
+
+fn parents(store: &dyn ObjectStore, commit: &Oid) -> crate::Result<Vec<Oid>> {
+    let object = store.get(commit)?;
+    let (_, commit) =
+        parsing::parse_commit(&object.contents).map_err(|error| Error::corrupt(*commit, error))?;
+    Ok(commit.parents)
+}
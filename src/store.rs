@@ -0,0 +1,767 @@
+use std::{
+    fmt, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    rc::Rc,
+    str::FromStr,
+};
+
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::{
+    bitmap::PackBitmap,
+    parsing::{Header, Type},
+    utils, Error, ALTERNATES, INFO, MAX_SHA_LEN, PACK,
+};
+
+mod loose;
+mod memory;
+mod packed;
+
+pub use loose::LooseStore;
+pub use memory::MemoryStore;
+pub use packed::PackStore;
+
+/// The hash function used to name objects, from extensions.objectFormat
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub enum ObjectFormat {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl ObjectFormat {
+    /// Number of bytes in a hash
+    pub fn hash_len(self) -> usize {
+        match self {
+            Self::Sha1 => 20,
+            Self::Sha256 => 32,
+        }
+    }
+
+    /// Number of hex digits in a hash
+    pub fn hex_len(self) -> usize {
+        self.hash_len() * 2
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Self::Sha1 => Hasher::Sha1(Sha1::new()),
+            Self::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+}
+
+impl fmt::Display for ObjectFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha1 => write!(f, "sha1"),
+            Self::Sha256 => write!(f, "sha256"),
+        }
+    }
+}
+
+impl FromStr for ObjectFormat {
+    type Err = crate::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "sha1" => Ok(Self::Sha1),
+            "sha256" => Ok(Self::Sha256),
+            _ => crate::bail!("unknown object format {name}"),
+        }
+    }
+}
+
+/// Hashes data with either of the object formats
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: impl AsRef<[u8]>) {
+        match self {
+            Self::Sha1(hasher) => hasher.update(data),
+            Self::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> Oid {
+        match self {
+            Self::Sha1(hasher) => <[u8; 20]>::from(hasher.finalize()).into(),
+            Self::Sha256(hasher) => <[u8; 32]>::from(hasher.finalize()).into(),
+        }
+    }
+}
+
+impl io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An object's id, the hash of its header and contents
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Oid {
+    // SHA-1 hashes are padded with zeros
+    hash: [u8; MAX_SHA_LEN],
+    format: ObjectFormat,
+}
+
+impl Oid {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.hash[..self.format.hash_len()]
+    }
+
+    pub fn format(&self) -> ObjectFormat {
+        self.format
+    }
+
+    /// Reads a hash in either format, depending on its length
+    pub fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        let format = match bytes.len() {
+            20 => ObjectFormat::Sha1,
+            32 => ObjectFormat::Sha256,
+            len => crate::bail!("invalid object hash length {len}"),
+        };
+
+        let mut hash = [0u8; MAX_SHA_LEN];
+        hash[..bytes.len()].copy_from_slice(bytes);
+        Ok(Self { hash, format })
+    }
+}
+
+impl From<[u8; 20]> for Oid {
+    fn from(hash: [u8; 20]) -> Self {
+        Self::from_bytes(&hash).expect("SHA-1 length")
+    }
+}
+
+impl From<[u8; 32]> for Oid {
+    fn from(hash: [u8; 32]) -> Self {
+        Self::from_bytes(&hash).expect("SHA-256 length")
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.as_bytes() {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Oid {
+    type Err = crate::Error;
+
+    fn from_str(hex: &str) -> Result<Self, Self::Err> {
+        crate::ensure!(
+            (hex.len() == ObjectFormat::Sha1.hex_len()
+                || hex.len() == ObjectFormat::Sha256.hex_len())
+                && hex.is_ascii(),
+            "invalid object hash {hex}"
+        );
+
+        let mut hash = vec![0u8; hex.len() / 2];
+        for (byte, digits) in hash.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).expect("ascii");
+            *byte = u8::from_str_radix(digits, 16)
+                .map_err(|_| Error::Other(format!("invalid object hash {hex}")))?;
+        }
+
+        Self::from_bytes(&hash)
+    }
+}
+
+#[derive(Clone)]
+pub struct Object {
+    pub r#type: Type,
+    pub contents: Vec<u8>,
+}
+
+impl Object {
+    pub fn oid(&self, format: ObjectFormat) -> Oid {
+        hash(format, self.r#type, &self.contents)
+    }
+}
+
+/// Computes an object's id without storing it
+pub fn hash(format: ObjectFormat, r#type: Type, contents: &[u8]) -> Oid {
+    let mut hasher = format.hasher();
+    hasher.update(format!("{type} {}\0", contents.len()));
+    hasher.update(contents);
+    hasher.finalize()
+}
+
+/// Computes the id of an object read from `contents`, which must produce exactly `size` bytes
+pub fn hash_stream(
+    format: ObjectFormat,
+    r#type: Type,
+    size: u64,
+    contents: &mut dyn Read,
+) -> crate::Result<Oid> {
+    let mut hasher = format.hasher();
+    hasher.update(format!("{type} {size}\0"));
+    let copied = io::copy(contents, &mut hasher)?;
+    if copied != size {
+        return Err(Error::CorruptObject {
+            oid: None,
+            reason: "object size is incorrect".into(),
+        });
+    }
+
+    Ok(hasher.finalize())
+}
+
+/// Storage for git objects
+pub trait ObjectStore {
+    /// Reads an object, returning `None` if it isn't in the store
+    fn read(&self, oid: &Oid) -> crate::Result<Option<Object>>;
+
+    /// Reads only an object's type and size
+    fn read_header(&self, oid: &Oid) -> crate::Result<Option<Header>> {
+        Ok(self.read(oid)?.map(|object| Header {
+            r#type: object.r#type,
+            size: object.contents.len(),
+        }))
+    }
+
+    /// Stores an object, returning its id
+    fn write(&self, r#type: Type, contents: &[u8]) -> crate::Result<Oid>;
+
+    /// Stores an object read from `contents`, which must produce exactly `size` bytes
+    fn write_stream(&self, r#type: Type, size: u64, contents: &mut dyn Read) -> crate::Result<Oid> {
+        let mut buf = Vec::with_capacity(size as usize);
+        contents.read_to_end(&mut buf)?;
+        if buf.len() as u64 != size {
+            return Err(Error::CorruptObject {
+                oid: None,
+                reason: "object size is incorrect".into(),
+            });
+        }
+
+        self.write(r#type, &buf)
+    }
+
+    fn exists(&self, oid: &Oid) -> crate::Result<bool>;
+
+    /// The hash function used to name this store's objects
+    fn format(&self) -> ObjectFormat;
+
+    /// Lists the id of every object in the store
+    fn iter(&self) -> crate::Result<Box<dyn Iterator<Item = Oid> + '_>>;
+
+    /// Lists the id of every object whose hex hash starts with `prefix`
+    fn find(&self, prefix: &str) -> crate::Result<Vec<Oid>> {
+        Ok(self
+            .iter()?
+            .filter(|oid| oid.to_string().starts_with(prefix))
+            .collect())
+    }
+
+    /// Expands a possibly abbreviated hash into the id of an object in the store
+    fn resolve(&self, hash: &str) -> crate::Result<Oid> {
+        // like git, hashes shorter than four digits don't name anything
+        if hash.len() < 4 {
+            return Err(Error::ObjectNotFound(hash.into()));
+        }
+
+        let mut candidates = self.find(hash)?;
+        match candidates.len() {
+            0 => return Err(Error::ObjectNotFound(hash.into())),
+            1 => return Ok(candidates[0]),
+            _ => {}
+        }
+
+        // like git, list tags first, then commits, trees and blobs
+        let mut types = Vec::with_capacity(candidates.len());
+        for oid in &candidates {
+            types.push(self.read_header(oid)?.map(|header| header.r#type));
+        }
+        let mut candidates = candidates.drain(..).zip(types).collect::<Vec<_>>();
+        candidates.sort_by_key(|&(oid, r#type)| {
+            let order = match r#type {
+                Some(Type::Tag) => 0,
+                Some(Type::Commit) => 1,
+                Some(Type::Tree) => 2,
+                Some(Type::Blob) => 3,
+                None => 4,
+            };
+            (order, oid)
+        });
+
+        Err(Error::AmbiguousId {
+            prefix: hash.into(),
+            candidates,
+        })
+    }
+
+    /// Shortens an object's hash to the fewest digits, but at least `min_len`,
+    /// that don't also match another object
+    fn abbreviate(&self, oid: &Oid, min_len: usize) -> crate::Result<String> {
+        let hash = oid.to_string();
+        let mut len = min_len.clamp(4, hash.len());
+        while len < hash.len() && self.find(&hash[..len])?.len() > 1 {
+            len += 1;
+        }
+
+        Ok(hash[..len].to_owned())
+    }
+
+    /// The reachability bitmaps of one of the store's packs, if it has any
+    fn bitmap(&self) -> crate::Result<Option<Rc<PackBitmap>>> {
+        Ok(None)
+    }
+
+    /// Reads an object which must be in the store
+    fn get(&self, oid: &Oid) -> crate::Result<Object> {
+        self.read(oid)?
+            .ok_or_else(|| Error::ObjectNotFound(oid.to_string()))
+    }
+}
+
+/// The .git database, where objects are read from both loose files and packs,
+/// then from the databases listed in objects/info/alternates, and new objects
+/// are written loose
+pub struct Database {
+    loose: LooseStore,
+    packs: PackStore,
+    /// Databases of other repositories that objects are borrowed from
+    alternates: Vec<Database>,
+}
+
+/// Alternates of alternates are followed up to this depth, like git
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
+impl Database {
+    pub fn open(objects: impl AsRef<Path>) -> crate::Result<Self> {
+        let objects = objects.as_ref();
+        if !objects.is_dir() {
+            return Err(Error::NotARepository(objects.into()));
+        }
+        let mut seen = vec![objects.canonicalize().unwrap_or_else(|_| objects.into())];
+        Self::open_with_alternates(objects, 0, &mut seen)
+    }
+
+    fn open_with_alternates(
+        objects: &Path,
+        depth: usize,
+        seen: &mut Vec<PathBuf>,
+    ) -> crate::Result<Self> {
+        let format = utils::object_format()?;
+
+        let mut paths = read_alternates(objects)?;
+        // like git, the whole chain stays usable, just without the deepest stores
+        if depth >= MAX_ALTERNATE_DEPTH && !paths.is_empty() {
+            eprintln!(
+                "warning: {}: ignored alternate object stores, nesting too deep",
+                objects.display()
+            );
+            paths.clear();
+        }
+
+        let mut alternates = vec![];
+        for path in paths {
+            // like git, a missing alternate is skipped so the rest of the
+            // repository can still be used
+            if !path.is_dir() {
+                eprintln!(
+                    "warning: object directory {} does not exist; check {}",
+                    path.display(),
+                    objects.join(INFO).join(ALTERNATES).display()
+                );
+                continue;
+            }
+            // a repository borrowing from one that borrows back is only read once
+            let canonical = path.canonicalize()?;
+            if seen.contains(&canonical) {
+                continue;
+            }
+            seen.push(canonical);
+            alternates.push(Self::open_with_alternates(&path, depth + 1, seen)?);
+        }
+
+        Ok(Self {
+            loose: LooseStore::new(objects, format).with_compression(utils::loose_compression()?),
+            packs: PackStore::open(objects.join(PACK), format)?
+                .with_compression(utils::pack_compression()?),
+            alternates,
+        })
+    }
+
+    pub fn loose(&self) -> &LooseStore {
+        &self.loose
+    }
+
+    pub fn packs(&self) -> &PackStore {
+        &self.packs
+    }
+
+    pub fn alternates(&self) -> &[Database] {
+        &self.alternates
+    }
+
+    /// Whether an object is stored in this database itself, rather than borrowed
+    pub fn has_local(&self, oid: &Oid) -> crate::Result<bool> {
+        Ok(self.loose.exists(oid)? || self.packs.exists(oid)?)
+    }
+
+    fn has_borrowed(&self, oid: &Oid) -> crate::Result<bool> {
+        for alternate in &self.alternates {
+            if alternate.exists(oid)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// The object directories listed in `objects`/info/alternates, one per line.
+/// Relative paths are relative to `objects`.
+pub fn read_alternates(objects: &Path) -> crate::Result<Vec<PathBuf>> {
+    let Ok(alternates) = fs::read_to_string(objects.join(INFO).join(ALTERNATES)) else {
+        return Ok(vec![]);
+    };
+
+    Ok(alternates
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| objects.join(line))
+        .collect())
+}
+
+impl ObjectStore for Database {
+    fn read(&self, oid: &Oid) -> crate::Result<Option<Object>> {
+        if let Some(object) = self.loose.read(oid)? {
+            return Ok(Some(object));
+        }
+        if let Some(object) = self.packs.read(oid)? {
+            return Ok(Some(object));
+        }
+        for alternate in &self.alternates {
+            if let Some(object) = alternate.read(oid)? {
+                return Ok(Some(object));
+            }
+        }
+        Ok(None)
+    }
+
+    fn read_header(&self, oid: &Oid) -> crate::Result<Option<Header>> {
+        if let Some(header) = self.loose.read_header(oid)? {
+            return Ok(Some(header));
+        }
+        if let Some(header) = self.packs.read_header(oid)? {
+            return Ok(Some(header));
+        }
+        for alternate in &self.alternates {
+            if let Some(header) = alternate.read_header(oid)? {
+                return Ok(Some(header));
+            }
+        }
+        Ok(None)
+    }
+
+    fn write(&self, r#type: Type, contents: &[u8]) -> crate::Result<Oid> {
+        let oid = hash(self.format(), r#type, contents);
+        if self.packs.exists(&oid)? || self.has_borrowed(&oid)? {
+            return Ok(oid);
+        }
+
+        self.loose.write(r#type, contents)
+    }
+
+    fn write_stream(&self, r#type: Type, size: u64, contents: &mut dyn Read) -> crate::Result<Oid> {
+        // like `write`, but the id is only known once everything has been read
+        self.loose
+            .write_stream_unless(r#type, size, contents, &|oid| {
+                Ok(self.packs.exists(oid)? || self.has_borrowed(oid)?)
+            })
+    }
+
+    fn exists(&self, oid: &Oid) -> crate::Result<bool> {
+        Ok(self.has_local(oid)? || self.has_borrowed(oid)?)
+    }
+
+    fn format(&self) -> ObjectFormat {
+        self.loose.format()
+    }
+
+    fn iter(&self) -> crate::Result<Box<dyn Iterator<Item = Oid> + '_>> {
+        let mut oids = self
+            .loose
+            .iter()?
+            .chain(self.packs.iter()?)
+            .collect::<Vec<_>>();
+        for alternate in &self.alternates {
+            oids.extend(alternate.iter()?);
+        }
+        oids.sort_unstable();
+        oids.dedup();
+        Ok(Box::new(oids.into_iter()))
+    }
+
+    fn find(&self, prefix: &str) -> crate::Result<Vec<Oid>> {
+        let mut oids = self.loose.find(prefix)?;
+        oids.extend(self.packs.find(prefix)?);
+        for alternate in &self.alternates {
+            oids.extend(alternate.find(prefix)?);
+        }
+        oids.sort_unstable();
+        oids.dedup();
+        Ok(oids)
+    }
+
+    fn bitmap(&self) -> crate::Result<Option<Rc<PackBitmap>>> {
+        self.packs.bitmap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::FORCE_SINGLE_THREAD;
+
+    use super::*;
+
+    #[test]
+    fn memory_store() {
+        let store = MemoryStore::default();
+
+        let oid = store.write(Type::Blob, b"Hello, world").unwrap();
+        assert_eq!(oid.to_string(), "dbe9dba55ea8fd4d5be3868b015e044be0848ec5");
+        assert_eq!(oid, hash(ObjectFormat::Sha1, Type::Blob, b"Hello, world"));
+        assert!(store.exists(&oid).unwrap());
+
+        let object = store.get(&oid).unwrap();
+        assert_eq!(object.r#type, Type::Blob);
+        assert_eq!(object.contents, b"Hello, world");
+
+        let other = store.write(Type::Blob, b"hello world").unwrap();
+        assert_eq!(store.iter().unwrap().count(), 2);
+        assert_eq!(store.abbreviate(&other, 7).unwrap(), "95d09f2");
+        assert_eq!(store.resolve("95d09").unwrap(), other);
+        assert!(matches!(
+            store.resolve("0000"),
+            Err(Error::ObjectNotFound(_))
+        ));
+        assert!(store.read(&[0u8; 20].into()).unwrap().is_none());
+        assert!(matches!(
+            Database::open("no_such_repository/.git/objects"),
+            Err(Error::NotARepository(_))
+        ));
+    }
+
+    #[test]
+    fn ambiguous_hashes() {
+        let store = MemoryStore::default();
+
+        let blob = store.write(Type::Blob, b"142").unwrap();
+        assert_eq!(blob.to_string(), "83248fb9df4a244c41a10fa536045d5a8e759aee");
+        let tree = store.write(Type::Tree, b"").unwrap();
+        assert_eq!(store.resolve("8324").unwrap(), blob);
+
+        let other = store.write(Type::Blob, b"784").unwrap();
+        assert_eq!(
+            other.to_string(),
+            "83249da5a136e0e0e9388917c65b042ef5179b5e"
+        );
+
+        let error = store.resolve("8324").unwrap_err();
+        assert!(matches!(&error, Error::AmbiguousId { candidates, .. } if candidates.len() == 2));
+        assert_eq!(
+            error.to_string(),
+            "\
+short object ID 8324 is ambiguous
+hint: The candidates are:
+hint:   83248 blob
+hint:   83249 blob"
+        );
+        assert_eq!(store.resolve("83249").unwrap(), other);
+
+        assert_eq!(store.abbreviate(&blob, 4).unwrap(), "83248");
+        assert_eq!(store.abbreviate(&tree, 4).unwrap(), "4b82");
+    }
+
+    #[test]
+    fn pack_store() {
+        const PACK_TEST_DIR: &str = "pack_store_test_dir";
+
+        let _guard = FORCE_SINGLE_THREAD.lock().unwrap();
+        let _ = fs::remove_dir_all(PACK_TEST_DIR);
+
+        let store = PackStore::open(PACK_TEST_DIR, ObjectFormat::Sha1).unwrap();
+        let blob = store.write(Type::Blob, b"hello world").unwrap();
+        let tree = store.write(Type::Tree, b"").unwrap();
+
+        // a fresh store finds both packs through their indexes
+        let store = PackStore::open(PACK_TEST_DIR, ObjectFormat::Sha1).unwrap();
+        assert_eq!(store.iter().unwrap().collect::<Vec<_>>(), [tree, blob]);
+        assert_eq!(store.resolve("95d0").unwrap(), blob);
+        assert_eq!(store.get(&blob).unwrap().contents, b"hello world");
+        assert_eq!(store.get(&tree).unwrap().r#type, Type::Tree);
+
+        fs::remove_dir_all(PACK_TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn stream_objects() {
+        const STREAM_TEST_DIR: &str = "stream_test_dir";
+
+        let _guard = FORCE_SINGLE_THREAD.lock().unwrap();
+        let _ = fs::remove_dir_all(STREAM_TEST_DIR);
+
+        let contents = b"Hello, world";
+        let size = contents.len() as u64;
+        let oid = hash(ObjectFormat::Sha1, Type::Blob, contents);
+        assert_eq!(
+            hash_stream(
+                ObjectFormat::Sha1,
+                Type::Blob,
+                size,
+                &mut contents.as_slice()
+            )
+            .unwrap(),
+            oid
+        );
+
+        let store = LooseStore::new(STREAM_TEST_DIR, ObjectFormat::Sha1);
+        let written = store
+            .write_stream(Type::Blob, size, &mut contents.as_slice())
+            .unwrap();
+        assert_eq!(written, oid);
+        assert_eq!(store.get(&oid).unwrap().contents, contents);
+
+        // a stream that doesn't match its size is rejected, leaving nothing behind
+        assert!(store
+            .write_stream(Type::Blob, size + 1, &mut contents.as_slice())
+            .is_err());
+        assert_eq!(store.iter().unwrap().count(), 1);
+        assert_eq!(
+            fs::read_dir(STREAM_TEST_DIR).unwrap().count(),
+            1,
+            "temporary file was left behind"
+        );
+
+        fs::remove_dir_all(STREAM_TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn loose_store_writes() {
+        const LOOSE_TEST_DIR: &str = "loose_store_test_dir";
+
+        let _guard = FORCE_SINGLE_THREAD.lock().unwrap();
+        let _ = fs::remove_dir_all(LOOSE_TEST_DIR);
+
+        let store = LooseStore::new(LOOSE_TEST_DIR, ObjectFormat::Sha1);
+        let oid = store.write(Type::Blob, b"Hello, world").unwrap();
+        let path = Path::new(LOOSE_TEST_DIR)
+            .join(&oid.to_string()[..2])
+            .join(&oid.to_string()[2..]);
+        assert!(fs::metadata(&path).unwrap().permissions().readonly());
+
+        // an existing object is left untouched by later writes
+        fs::remove_file(&path).unwrap();
+        fs::write(&path, b"marker").unwrap();
+        assert_eq!(store.write(Type::Blob, b"Hello, world").unwrap(), oid);
+        assert_eq!(fs::read(&path).unwrap(), b"marker");
+
+        // no temporary files are left behind
+        assert_eq!(fs::read_dir(LOOSE_TEST_DIR).unwrap().count(), 1);
+
+        fs::remove_dir_all(LOOSE_TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn borrow_from_alternates() {
+        const ALTERNATES_TEST_DIR: &str = "alternates_test_dir";
+
+        let _guard = FORCE_SINGLE_THREAD.lock().unwrap();
+        let _ = fs::remove_dir_all(ALTERNATES_TEST_DIR);
+
+        let shared = Path::new(ALTERNATES_TEST_DIR).join("shared");
+        let local = Path::new(ALTERNATES_TEST_DIR).join("local");
+        let borrowed = LooseStore::new(&shared, ObjectFormat::Sha1)
+            .write(Type::Blob, b"shared")
+            .unwrap();
+        fs::create_dir_all(local.join(INFO)).unwrap();
+        fs::write(local.join(INFO).join(ALTERNATES), "# comment\n../shared\n").unwrap();
+        // borrowing back from the borrowing database doesn't loop forever
+        fs::create_dir_all(shared.join(INFO)).unwrap();
+        fs::write(shared.join(INFO).join(ALTERNATES), "../local\n").unwrap();
+
+        let store = Database::open(&local).unwrap();
+        assert_eq!(store.alternates().len(), 1);
+        assert_eq!(store.get(&borrowed).unwrap().contents, b"shared");
+        assert!(store.exists(&borrowed).unwrap());
+        assert!(!store.has_local(&borrowed).unwrap());
+
+        // borrowed objects aren't copied when they're written again
+        assert_eq!(store.write(Type::Blob, b"shared").unwrap(), borrowed);
+        assert!(!store.has_local(&borrowed).unwrap());
+        let streamed = store
+            .write_stream(Type::Blob, 6, &mut &b"shared"[..])
+            .unwrap();
+        assert_eq!(streamed, borrowed);
+        assert!(!store.has_local(&borrowed).unwrap());
+        let own = store.write(Type::Blob, b"local").unwrap();
+        assert!(store.has_local(&own).unwrap());
+        assert_eq!(store.iter().unwrap().count(), 2);
+
+        fs::write(local.join(INFO).join(ALTERNATES), "../missing\n../shared\n").unwrap();
+        let store = Database::open(&local).unwrap();
+        assert_eq!(store.alternates().len(), 1);
+        assert!(store.exists(&borrowed).unwrap());
+
+        // a chain deeper than git follows is cut off instead of failing
+        let chain = (0..=MAX_ALTERNATE_DEPTH + 1)
+            .map(|link| Path::new(ALTERNATES_TEST_DIR).join(format!("chain{link}")))
+            .collect::<Vec<_>>();
+        for link in 0..chain.len() {
+            fs::create_dir_all(chain[link].join(INFO)).unwrap();
+            if link + 1 < chain.len() {
+                fs::write(
+                    chain[link].join(INFO).join(ALTERNATES),
+                    format!("../chain{}\n", link + 1),
+                )
+                .unwrap();
+            }
+        }
+        let mut store = &Database::open(&chain[0]).unwrap();
+        for _ in 0..MAX_ALTERNATE_DEPTH {
+            store = &store.alternates()[0];
+        }
+        assert!(store.alternates().is_empty());
+
+        fs::remove_dir_all(ALTERNATES_TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn parse_oid() {
+        let hex = "dbe9dba55ea8fd4d5be3868b015e044be0848ec5";
+        let oid = hex.parse::<Oid>().unwrap();
+        assert_eq!(oid.to_string(), hex);
+
+        assert_eq!(oid.format(), ObjectFormat::Sha1);
+
+        let hex = "af6c6c31f96fefd60b38eee217c3704e4f29c01b9e59acf07bc69a279a60c580";
+        let oid = hex.parse::<Oid>().unwrap();
+        assert_eq!(oid.to_string(), hex);
+        assert_eq!(oid.format(), ObjectFormat::Sha256);
+        assert_eq!(hash(ObjectFormat::Sha256, Type::Blob, b"Hello, world"), oid);
+
+        assert!("dbe9dba5".parse::<Oid>().is_err());
+        assert!("zbe9dba55ea8fd4d5be3868b015e044be0848ec5"
+            .parse::<Oid>()
+            .is_err());
+    }
+}
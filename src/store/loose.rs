@@ -0,0 +1,292 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::SystemTime,
+};
+
+use flate2::{read::ZlibDecoder, write, Compression};
+
+use crate::{
+    parsing::{self, Header, Type},
+    Error,
+};
+
+use super::{hash, Object, ObjectFormat, ObjectStore, Oid};
+
+/// Objects stored as individual zlib-compressed files, named by their hash
+pub struct LooseStore {
+    objects: PathBuf,
+    format: ObjectFormat,
+    compression: Compression,
+}
+
+impl LooseStore {
+    pub fn new(objects: impl Into<PathBuf>, format: ObjectFormat) -> Self {
+        Self {
+            objects: objects.into(),
+            format,
+            compression: Compression::default(),
+        }
+    }
+
+    /// Sets the zlib level used for new objects
+    pub fn with_compression(self, compression: Compression) -> Self {
+        Self {
+            compression,
+            ..self
+        }
+    }
+
+    fn path(&self, oid: &Oid) -> PathBuf {
+        let hash = oid.to_string();
+        let (sha_dir, sha_file) = hash.split_at(2);
+        self.objects.join(sha_dir).join(sha_file)
+    }
+
+    /// A unique path for writing an object before its name is known
+    fn temp_path(&self) -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        self.objects
+            .join(format!("tmp_obj_{}_{count}", process::id()))
+    }
+
+    /// Compress an object into a temporary file, then move it into place under
+    /// the name returned by `write`, unless `stored` says it's already elsewhere.
+    /// Readers never see a partially written object.
+    fn write_temp(
+        &self,
+        write: impl FnOnce(&mut write::ZlibEncoder<BufWriter<File>>) -> crate::Result<Oid>,
+        stored: &dyn Fn(&Oid) -> crate::Result<bool>,
+    ) -> crate::Result<Oid> {
+        fs::create_dir_all(&self.objects)?;
+        let temp_path = self.temp_path();
+
+        let result = (|| {
+            let mut compressor = write::ZlibEncoder::new(
+                BufWriter::new(File::create(&temp_path)?),
+                self.compression,
+            );
+            let oid = write(&mut compressor)?;
+            let file = compressor
+                .finish()?
+                .into_inner()
+                .map_err(io::IntoInnerError::into_error)?;
+            file.sync_all()?;
+
+            // objects are never modified, so make them read-only like git does
+            let mut permissions = file.metadata()?.permissions();
+            permissions.set_readonly(true);
+            file.set_permissions(permissions)?;
+
+            let path = self.path(&oid);
+            if path.is_file() || stored(&oid)? {
+                // another writer got there first, or the object is stored elsewhere
+                fs::remove_file(&temp_path)?;
+            } else {
+                fs::create_dir_all(path.parent().expect("object is in a subdirectory"))?;
+                fs::rename(&temp_path, path)?;
+            }
+
+            Ok(oid)
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    /// When an object was last written
+    pub fn modified(&self, oid: &Oid) -> crate::Result<SystemTime> {
+        Ok(fs::metadata(self.path(oid))?.modified()?)
+    }
+
+    /// Deletes an object, along with its directory if that becomes empty
+    pub fn remove(&self, oid: &Oid) -> crate::Result<()> {
+        let path = self.path(oid);
+        fs::remove_file(&path)?;
+        let _ = fs::remove_dir(path.parent().expect("object is in a subdirectory"));
+
+        Ok(())
+    }
+
+    /// Stores an object read from `contents` like `write_stream`, unless `stored`
+    /// finds it elsewhere once it's been hashed
+    pub fn write_stream_unless(
+        &self,
+        r#type: Type,
+        size: u64,
+        contents: &mut dyn Read,
+        stored: &dyn Fn(&Oid) -> crate::Result<bool>,
+    ) -> crate::Result<Oid> {
+        // hash and compress in a single pass, since the object's name
+        // isn't known until everything has been read
+        self.write_temp(
+            |compressor| {
+                let mut hasher = self.format.hasher();
+
+                let header = format!("{type} {size}\0");
+                hasher.update(&header);
+                compressor.write_all(header.as_bytes())?;
+
+                let mut buf = vec![0u8; 64 * 1024];
+                let mut copied = 0;
+                loop {
+                    let count = contents.read(&mut buf)?;
+                    if count == 0 {
+                        break;
+                    }
+                    hasher.update(&buf[..count]);
+                    compressor.write_all(&buf[..count])?;
+                    copied += count as u64;
+                }
+                if copied != size {
+                    return Err(Error::CorruptObject {
+                        oid: None,
+                        reason: "object size is incorrect".into(),
+                    });
+                }
+
+                Ok(hasher.finalize())
+            },
+            stored,
+        )
+    }
+
+    fn open(&self, oid: &Oid) -> crate::Result<Option<ZlibDecoder<File>>> {
+        match File::open(self.path(oid)) {
+            Ok(file) => Ok(Some(ZlibDecoder::new(file))),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+impl ObjectStore for LooseStore {
+    fn read(&self, oid: &Oid) -> crate::Result<Option<Object>> {
+        let Some(mut decoder) = self.open(oid)? else {
+            return Ok(None);
+        };
+
+        // possible optimization: read up to the filesize,
+        // then perform just one allocation for the next read
+        let mut buf = vec![];
+        decoder
+            .read_to_end(&mut buf)
+            .map_err(|error| Error::CorruptObject {
+                oid: Some(*oid),
+                reason: error.to_string(),
+            })?;
+        let (contents, r#type) =
+            parsing::parse_contents(&buf).map_err(|error| Error::corrupt(*oid, error))?;
+
+        Ok(Some(Object {
+            r#type,
+            contents: contents.to_vec(),
+        }))
+    }
+
+    fn read_header(&self, oid: &Oid) -> crate::Result<Option<Header>> {
+        let Some(decoder) = self.open(oid)? else {
+            return Ok(None);
+        };
+
+        // the header is short, so only decompress the first few bytes
+        let mut buf = vec![];
+        decoder.take(64).read_to_end(&mut buf)?;
+        let (_, header) =
+            parsing::parse_header(&buf).map_err(|error| Error::corrupt(*oid, error))?;
+
+        Ok(Some(header))
+    }
+
+    fn write(&self, r#type: Type, contents: &[u8]) -> crate::Result<Oid> {
+        let oid = hash(self.format, r#type, contents);
+
+        // objects are named by their contents, so an existing one is already correct
+        if self.exists(&oid)? {
+            return Ok(oid);
+        }
+
+        self.write_temp(
+            |compressor| {
+                write!(compressor, "{type} {}\0", contents.len())?;
+                compressor.write_all(contents)?;
+                Ok(oid)
+            },
+            &|_| Ok(false),
+        )
+    }
+
+    fn write_stream(&self, r#type: Type, size: u64, contents: &mut dyn Read) -> crate::Result<Oid> {
+        self.write_stream_unless(r#type, size, contents, &|_| Ok(false))
+    }
+
+    fn exists(&self, oid: &Oid) -> crate::Result<bool> {
+        Ok(self.path(oid).is_file())
+    }
+
+    fn format(&self) -> ObjectFormat {
+        self.format
+    }
+
+    fn iter(&self) -> crate::Result<Box<dyn Iterator<Item = Oid> + '_>> {
+        let mut oids = vec![];
+
+        let Ok(entries) = fs::read_dir(&self.objects) else {
+            return Ok(Box::new(oids.into_iter()));
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let sha_dir = entry.file_name().to_string_lossy().into_owned();
+            if sha_dir.len() != 2 || !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            for entry in fs::read_dir(entry.path())?.filter_map(Result::ok) {
+                let sha_file = entry.file_name();
+                let sha_file = sha_file.to_string_lossy();
+                if sha_file.len() == self.format.hex_len() - 2 {
+                    if let Ok(oid) = format!("{sha_dir}{sha_file}").parse() {
+                        oids.push(oid);
+                    }
+                }
+            }
+        }
+
+        Ok(Box::new(oids.into_iter()))
+    }
+
+    fn find(&self, prefix: &str) -> crate::Result<Vec<Oid>> {
+        if prefix.len() < 2 || !prefix.is_char_boundary(2) {
+            return Ok(vec![]);
+        }
+        let (sha_dir, sha_file) = prefix.split_at(2);
+
+        let Ok(entries) = fs::read_dir(self.objects.join(sha_dir)) else {
+            return Ok(vec![]);
+        };
+
+        Ok(entries
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                entry.file_name().len() == self.format.hex_len() - 2
+                    && entry
+                        .file_name()
+                        .as_os_str()
+                        .to_string_lossy()
+                        .starts_with(sha_file)
+            })
+            .filter_map(|entry| {
+                format!("{sha_dir}{}", entry.file_name().to_string_lossy())
+                    .parse()
+                    .ok()
+            })
+            .collect())
+    }
+}
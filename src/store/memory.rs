@@ -0,0 +1,52 @@
+use std::{cell::RefCell, collections::BTreeMap};
+
+use crate::parsing::Type;
+
+use super::{hash, Object, ObjectFormat, ObjectStore, Oid};
+
+/// Objects kept in memory, which are lost when the store is dropped
+#[derive(Default)]
+pub struct MemoryStore {
+    objects: RefCell<BTreeMap<Oid, Object>>,
+    format: ObjectFormat,
+}
+
+impl MemoryStore {
+    pub fn new(format: ObjectFormat) -> Self {
+        Self {
+            objects: Default::default(),
+            format,
+        }
+    }
+}
+
+impl ObjectStore for MemoryStore {
+    fn read(&self, oid: &Oid) -> crate::Result<Option<Object>> {
+        Ok(self.objects.borrow().get(oid).cloned())
+    }
+
+    fn write(&self, r#type: Type, contents: &[u8]) -> crate::Result<Oid> {
+        let oid = hash(self.format, r#type, contents);
+        self.objects
+            .borrow_mut()
+            .entry(oid)
+            .or_insert_with(|| Object {
+                r#type,
+                contents: contents.to_vec(),
+            });
+        Ok(oid)
+    }
+
+    fn exists(&self, oid: &Oid) -> crate::Result<bool> {
+        Ok(self.objects.borrow().contains_key(oid))
+    }
+
+    fn format(&self) -> ObjectFormat {
+        self.format
+    }
+
+    fn iter(&self) -> crate::Result<Box<dyn Iterator<Item = Oid> + '_>> {
+        let oids = self.objects.borrow().keys().copied().collect::<Vec<_>>();
+        Ok(Box::new(oids.into_iter()))
+    }
+}
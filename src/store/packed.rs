@@ -0,0 +1,316 @@
+use std::{
+    cell::{OnceCell, RefCell},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use flate2::Compression;
+
+use crate::{
+    bitmap::{self, PackBitmap},
+    pack::{self, DeltaLimits, Index, IndexEntry},
+    parsing::{Header, Type},
+    utils, Error,
+};
+
+use super::{hash, Object, ObjectFormat, ObjectStore, Oid};
+
+/// Objects stored in packfiles, found through each pack's index
+pub struct PackStore {
+    dir: PathBuf,
+    format: ObjectFormat,
+    packs: RefCell<Vec<Pack>>,
+    compression: Compression,
+}
+
+struct Pack {
+    path: PathBuf,
+    index: Index,
+    /// The pack's bitmaps, once something has looked for them
+    bitmap: OnceCell<Option<Rc<PackBitmap>>>,
+}
+
+impl PackStore {
+    /// Loads the index of every pack in `dir`, which doesn't need to exist yet
+    pub fn open(dir: impl Into<PathBuf>, format: ObjectFormat) -> crate::Result<Self> {
+        let dir = dir.into();
+        let mut packs = vec![];
+
+        if let Ok(entries) = fs::read_dir(&dir) {
+            for entry in entries.filter_map(Result::ok) {
+                let path = entry.path();
+                if path.extension().is_some_and(|extension| extension == "idx") {
+                    packs.push(Pack {
+                        index: Index::open(&path, format)?,
+                        path: path.with_extension("pack"),
+                        bitmap: OnceCell::new(),
+                    });
+                }
+            }
+        }
+
+        Ok(Self {
+            dir,
+            format,
+            packs: RefCell::new(packs),
+            compression: Compression::default(),
+        })
+    }
+
+    /// Sets the zlib level used for new packs
+    pub fn with_compression(self, compression: Compression) -> Self {
+        Self {
+            compression,
+            ..self
+        }
+    }
+
+    /// Stores a packfile and an index built from its entries, returning the pack's path
+    pub fn add_pack(&self, pack: &[u8], entries: Vec<IndexEntry>) -> crate::Result<PathBuf> {
+        let len = self.format.hash_len();
+        crate::ensure!(pack.len() >= len, CorruptPack, "pack file is truncated");
+        let checksum = Oid::from_bytes(&pack[pack.len() - len..])?;
+
+        fs::create_dir_all(&self.dir)?;
+        let path = self.pack_path(&checksum);
+        utils::write_atomic(&path, |file| Ok(file.write_all(pack)?))?;
+        self.add_index(path, entries, &checksum)
+    }
+
+    /// Reads objects from `store` and stores them in a new pack, as deltas of
+    /// each other where possible. Each object comes with the file name it was
+    /// found under. The pack is written as the objects are read, rather than
+    /// built in memory first.
+    pub fn write_objects(
+        &self,
+        store: &dyn ObjectStore,
+        objects: &[(Oid, String)],
+        limits: DeltaLimits,
+    ) -> crate::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        // packs are named after their checksum, which is only known at the end
+        let (entries, checksum) = utils::write_atomic_named(
+            &self.dir,
+            "pack",
+            |file| pack::pack_objects(store, objects, limits, self.compression, file),
+            |(_, checksum)| self.pack_path(checksum),
+        )?;
+        self.add_index(self.pack_path(&checksum), entries, &checksum)
+    }
+
+    fn pack_path(&self, checksum: &Oid) -> PathBuf {
+        self.dir.join(format!("pack-{checksum}.pack"))
+    }
+
+    /// Writes the index of a pack that's already in place, and starts reading from it
+    fn add_index(
+        &self,
+        path: PathBuf,
+        entries: Vec<IndexEntry>,
+        checksum: &Oid,
+    ) -> crate::Result<PathBuf> {
+        // the index is written last, since readers find packs through it
+        let index_path = path.with_extension("idx");
+        utils::write_atomic(&index_path, |file| {
+            pack::write_index(entries, checksum, file)
+        })?;
+
+        // writing the same objects again produces a pack that's already loaded
+        let index = Index::open(index_path, self.format)?;
+        let mut packs = self.packs.borrow_mut();
+        if !packs.iter().any(|pack| pack.path == path) {
+            packs.push(Pack {
+                path: path.clone(),
+                index,
+                bitmap: OnceCell::new(),
+            });
+        }
+
+        Ok(path)
+    }
+
+    /// Paths of the packfiles in the store
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.packs
+            .borrow()
+            .iter()
+            .map(|pack| pack.path.clone())
+            .collect()
+    }
+
+    /// Writes reachability bitmaps for a pack in the store, which must contain
+    /// every object reachable from `roots`. Objects are read from `store`.
+    pub fn write_bitmap(
+        &self,
+        path: &Path,
+        store: &dyn ObjectStore,
+        roots: &[Oid],
+    ) -> crate::Result<()> {
+        let packs = self.packs.borrow();
+        let pack = packs
+            .iter()
+            .find(|pack| pack.path == path)
+            .ok_or_else(|| Error::Other(format!("{} is not in the store", path.display())))?;
+
+        utils::write_atomic(&path.with_extension("bitmap"), |file| {
+            bitmap::write_bitmap(store, &pack.index, roots, file)
+        })?;
+        drop(packs);
+
+        // the new bitmaps replace whatever was read before
+        for pack in self.packs.borrow_mut().iter_mut() {
+            if pack.path == path {
+                pack.bitmap.take();
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes a packfile, its index and its bitmaps
+    pub fn remove_pack(&self, path: &Path) -> crate::Result<()> {
+        self.packs.borrow_mut().retain(|pack| pack.path != path);
+
+        // bitmaps and the index go first, so readers never find a pack that's missing
+        match fs::remove_file(path.with_extension("bitmap")) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
+        }
+        fs::remove_file(path.with_extension("idx"))?;
+        fs::remove_file(path)?;
+
+        Ok(())
+    }
+
+    /// Reads an object that is `depth` deltas into a chain
+    fn read_at_depth(&self, oid: &Oid, depth: usize) -> crate::Result<Option<Object>> {
+        let Some((path, offset)) = self.locate(oid)? else {
+            return Ok(None);
+        };
+
+        // packs on disk are self-contained, so bases of ref deltas are in this store
+        let find_base = |base: &Oid, depth| {
+            self.read_at_depth(base, depth)?
+                .ok_or_else(|| Error::ObjectNotFound(base.to_string()))
+        };
+        Ok(Some(pack::read_object(
+            &path,
+            offset,
+            self.format,
+            depth,
+            &find_base,
+        )?))
+    }
+
+    /// Reads the header of an object that is `depth` deltas into a chain
+    fn read_header_at_depth(&self, oid: &Oid, depth: usize) -> crate::Result<Option<Header>> {
+        let Some((path, offset)) = self.locate(oid)? else {
+            return Ok(None);
+        };
+
+        let find_base = |base: &Oid, depth| {
+            self.read_header_at_depth(base, depth)?
+                .ok_or_else(|| Error::ObjectNotFound(base.to_string()))
+        };
+        Ok(Some(pack::read_header(
+            &path,
+            offset,
+            self.format,
+            depth,
+            &find_base,
+        )?))
+    }
+
+    fn locate(&self, oid: &Oid) -> crate::Result<Option<(PathBuf, u64)>> {
+        for pack in self.packs.borrow().iter() {
+            if let Some(position) = pack.index.position(oid) {
+                return Ok(Some((pack.path.clone(), pack.index.offset(position)?)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl ObjectStore for PackStore {
+    fn read(&self, oid: &Oid) -> crate::Result<Option<Object>> {
+        self.read_at_depth(oid, 0)
+    }
+
+    fn read_header(&self, oid: &Oid) -> crate::Result<Option<Header>> {
+        self.read_header_at_depth(oid, 0)
+    }
+
+    fn write(&self, r#type: Type, contents: &[u8]) -> crate::Result<Oid> {
+        let oid = hash(self.format, r#type, contents);
+        if self.exists(&oid)? {
+            return Ok(oid);
+        }
+
+        let object = Object {
+            r#type,
+            contents: contents.to_vec(),
+        };
+        let mut pack = vec![];
+        let (entries, _) = pack::write_pack(&[object], self.format, self.compression, &mut pack)?;
+        self.add_pack(&pack, entries)?;
+
+        Ok(oid)
+    }
+
+    fn exists(&self, oid: &Oid) -> crate::Result<bool> {
+        Ok(self
+            .packs
+            .borrow()
+            .iter()
+            .any(|pack| pack.index.position(oid).is_some()))
+    }
+
+    fn format(&self) -> ObjectFormat {
+        self.format
+    }
+
+    fn iter(&self) -> crate::Result<Box<dyn Iterator<Item = Oid> + '_>> {
+        let mut oids = vec![];
+        for pack in self.packs.borrow().iter() {
+            oids.extend((0..pack.index.len()).map(|position| pack.index.oid(position)));
+        }
+        oids.sort_unstable();
+        oids.dedup();
+
+        Ok(Box::new(oids.into_iter()))
+    }
+
+    fn find(&self, prefix: &str) -> crate::Result<Vec<Oid>> {
+        let mut oids = vec![];
+        for pack in self.packs.borrow().iter() {
+            oids.extend(
+                pack.index
+                    .find(prefix)
+                    .into_iter()
+                    .map(|position| pack.index.oid(position)),
+            );
+        }
+        oids.sort_unstable();
+        oids.dedup();
+
+        Ok(oids)
+    }
+
+    /// The bitmaps of the first pack that has them
+    fn bitmap(&self) -> crate::Result<Option<Rc<PackBitmap>>> {
+        for pack in self.packs.borrow().iter() {
+            let bitmap = match pack.bitmap.get() {
+                Some(bitmap) => bitmap.clone(),
+                None => {
+                    let bitmap = PackBitmap::open(&pack.path, self.format)?.map(Rc::new);
+                    pack.bitmap.get_or_init(|| bitmap).clone()
+                }
+            };
+            if bitmap.is_some() {
+                return Ok(bitmap);
+            }
+        }
+        Ok(None)
+    }
+}
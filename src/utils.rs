@@ -1,16 +1,28 @@
 use std::{
     cell::Cell,
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
     fmt,
     fs::{self, File},
-    io::{self, Read, Write},
+    io::{self, BufWriter, Write},
     mem,
     path::{Path, PathBuf},
+    process,
+    sync::atomic::{self, AtomicUsize},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{ensure, Context};
-use flate2::read::ZlibDecoder;
+use flate2::Compression;
 
-use crate::{parsing, CONFIG, DOT_GIT, HEAD, OBJECTS, SHA_DISPLAY_LEN, SHA_LEN};
+use crate::{
+    cache::ObjectCache,
+    parsing,
+    store::{ObjectFormat, ObjectStore, Oid},
+    Error, CONFIG, DOT_GIT, HEAD, LOGS, PACKED_REFS, REFS,
+};
+
+/// The tree entry mode of a submodule commit, which lives in another repository
+pub const GITLINK: u32 = 160_000;
 
 #[derive(Clone, Copy)]
 pub struct EntryDisplay {
@@ -19,10 +31,11 @@ pub struct EntryDisplay {
     pub abbrev: u8,
 }
 
+#[derive(Clone)]
 pub struct Entry {
     pub display: Cell<Option<EntryDisplay>>,
     pub mode: u32,
-    pub hash: [u8; SHA_LEN],
+    pub hash: Oid,
     pub name: String,
     pub tree: bool,
     pub children: Option<Vec<Entry>>,
@@ -42,17 +55,13 @@ impl fmt::Display for Entry {
         if !display.name_only {
             write!(f, "{:06}\t", self.mode)?;
             write!(f, "{}\t", if self.tree { "tree" } else { "blob" })?;
-            for byte in &self.hash[..display.abbrev as usize] {
-                write!(f, "{byte:02x}")?;
-            }
-            write!(f, "\t")?;
+            write!(f, "{}\t", &self.hash.to_string()[..display.abbrev as usize])?;
         }
 
         writeln!(f, "{}", self.name)?;
 
         if let Some(children) = self.children.as_deref() {
             for child in children {
-                child.display.set(self.display.get());
                 write!(f, "{child}")?;
             }
         }
@@ -61,114 +70,463 @@ impl fmt::Display for Entry {
     }
 }
 
-pub fn find_object(hash: &str) -> anyhow::Result<PathBuf> {
-    let failed_context = || format!("failed to find {hash}");
-
-    ensure!(hash.len() > 3, "object hash is not long enough");
-    let (sha_dir, sha_file) = hash.split_at(2);
-
-    let entries = fs::read_dir(Path::new(DOT_GIT).join(OBJECTS))?;
-
-    let entry = entries
-        .filter_map(Result::ok)
-        .find(|entry| sha_dir == entry.file_name())
-        .with_context(failed_context)?;
-
-    let entries = fs::read_dir(entry.path())?;
-
-    let entry = entries
-        .filter_map(Result::ok)
-        .find(|entry| {
-            entry.file_name().len() == SHA_DISPLAY_LEN - 2
-                && entry
-                    .file_name()
-                    .as_os_str()
-                    .to_string_lossy()
-                    .starts_with(sha_file)
-        })
-        .with_context(failed_context)?;
+impl Entry {
+    /// Compares entries in the order git keeps them in trees
+    pub fn cmp_tree_order(&self, other: &Self) -> Ordering {
+        tree_order(
+            (self.name.as_bytes(), self.tree),
+            (other.name.as_bytes(), other.tree),
+        )
+    }
+}
 
-    Ok(entry.path())
+/// Compares tree entries, given as their name and whether they're a tree, in
+/// the order git keeps them in trees, where a tree sorts as though its name
+/// ended with '/'. So "a.txt" comes before the tree "a", but after the file "a".
+pub fn tree_order(
+    (left, left_tree): (&[u8], bool),
+    (right, right_tree): (&[u8], bool),
+) -> Ordering {
+    let left = left.iter().chain(left_tree.then_some(&b'/'));
+    let right = right.iter().chain(right_tree.then_some(&b'/'));
+    left.cmp(right)
 }
 
-pub fn create_object(hash: &[u8; SHA_LEN]) -> anyhow::Result<File> {
-    let mut path: PathBuf = [DOT_GIT, OBJECTS, &format!("{:02x}", &hash[0])]
-        .iter()
-        .collect();
+pub fn hex(bytes: &[u8]) -> String {
+    use std::fmt::Write; // prevent conflict with io::Write
 
-    if let Err(error) = fs::create_dir(&path) {
-        ensure!(
-            error.kind() == io::ErrorKind::AlreadyExists,
-            "failed to create object subdirectory"
-        );
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{byte:02x}").expect("writing to a string");
     }
+    hex
+}
 
-    path.push({
-        use std::fmt::Write; //here to prevent conflict with io::Write
+/// The entries of a tree, and with `recurse`, of its subtrees as their children
+pub fn tree_level(cache: &ObjectCache, oid: &Oid, recurse: bool) -> crate::Result<Vec<Entry>> {
+    let mut entries = cache.tree(oid)?.to_vec();
 
-        let mut filename = String::with_capacity(SHA_DISPLAY_LEN - 2);
-        for byte in &hash[1..] {
-            write!(filename, "{byte:02x}")?;
+    if recurse {
+        for entry in entries.iter_mut().filter(|entry| entry.tree) {
+            entry.children = Some(tree_level(cache, &entry.hash, true)?);
         }
-        filename
-    });
-
-    // remove an existing file to clear permissions
-    if let Err(error) = fs::remove_file(&path) {
-        ensure!(
-            error.kind() == io::ErrorKind::NotFound,
-            "failed to remove object"
-        );
     }
 
-    Ok(File::create(path)?)
+    Ok(entries)
 }
 
-pub fn tree_level(hash: &str, recurse: bool) -> anyhow::Result<Vec<Entry>> {
-    let path = find_object(hash)?;
-
-    let mut buf = vec![];
-    ZlibDecoder::new(File::open(path)?).read_to_end(&mut buf)?;
-
-    let (_, entries) = parsing::parse_tree(recurse)(&buf)?;
-
-    Ok(entries)
+/// Minimum length of abbreviated hashes, from core.abbrev. By default this
+/// grows with the number of objects, like git's "auto" setting.
+pub fn default_abbrev(store: &dyn ObjectStore) -> crate::Result<usize> {
+    match get_config_value("core", "abbrev")?.as_deref() {
+        None | Some("auto") => {
+            let count = store.iter()?.count() as u64;
+            let bits = u64::BITS - count.leading_zeros();
+            Ok((bits as usize).div_ceil(2).max(7))
+        }
+        Some("no" | "false") => Ok(store.format().hex_len()),
+        Some(abbrev) => Ok(abbrev
+            .parse::<usize>()
+            .map_err(|_| Error::Config("core.abbrev must be a number, \"auto\" or \"no\"".into()))?
+            .clamp(4, store.format().hex_len())),
+    }
 }
 
-pub fn get_head() -> anyhow::Result<Option<String>> {
+pub fn get_head() -> crate::Result<Option<String>> {
     let head_file = fs::read_to_string(Path::new(DOT_GIT).join(HEAD))?;
     let head_ref_at = Path::new(DOT_GIT).join(
         head_file
             .trim()
             .strip_prefix("ref: ")
-            .context("detached HEAD")?,
+            .ok_or_else(|| Error::invalid_ref(HEAD, "not a symbolic ref"))?,
     );
     Ok(fs::read_to_string(head_ref_at).ok())
 }
 
-pub fn update_head(commit_hash: &str) -> anyhow::Result<()> {
+pub fn update_head(commit_hash: &str) -> crate::Result<()> {
     let head_file = fs::read_to_string(Path::new(DOT_GIT).join(HEAD))?;
     let head_ref_at = Path::new(DOT_GIT).join(
         head_file
             .trim()
             .strip_prefix("ref: ")
-            .context("detached HEAD")?,
+            .ok_or_else(|| Error::invalid_ref(HEAD, "not a symbolic ref"))?,
     );
     Ok(fs::write(head_ref_at, commit_hash)?)
 }
 
-pub fn get_config_value(section: &str, key: &str) -> anyhow::Result<Option<String>> {
-    Ok(read_config()?
+/// Every object that refs, HEAD and the reflogs point to, which keep history alive
+pub fn root_objects() -> crate::Result<Vec<Oid>> {
+    let mut roots = list_refs()?
+        .into_iter()
+        .chain(reflog_entries()?)
+        .map(|(_, oid)| oid)
+        .chain(resolve_head()?)
+        .collect::<Vec<_>>();
+    roots.sort_unstable();
+    roots.dedup();
+
+    Ok(roots)
+}
+
+/// Every object reachable from `roots` without going through an `excluded` one,
+/// with the name of the tree entry it was first found under, or an empty name
+/// for commits and tags
+pub fn reachable_objects(
+    store: &dyn ObjectStore,
+    roots: &[Oid],
+    excluded: &BTreeSet<Oid>,
+) -> crate::Result<Vec<(Oid, String)>> {
+    let mut seen = BTreeSet::new();
+    let mut objects = vec![];
+    let mut stack = roots
+        .iter()
+        .rev()
+        .map(|oid| (*oid, String::new()))
+        .collect::<Vec<_>>();
+
+    while let Some((oid, name)) = stack.pop() {
+        if excluded.contains(&oid) || !seen.insert(oid) {
+            continue;
+        }
+        let object = store.get(&oid)?;
+
+        match object.r#type {
+            parsing::Type::Blob => {}
+            parsing::Type::Tree => {
+                let (_, entries) = parsing::parse_tree(store.format())(&object.contents)?;
+                for entry in entries.into_iter().rev() {
+                    if entry.mode != GITLINK {
+                        stack.push((entry.hash, entry.name));
+                    }
+                }
+            }
+            parsing::Type::Commit => {
+                let (_, commit) = parsing::parse_commit(&object.contents)?;
+                stack.extend(commit.parents.iter().map(|parent| (*parent, String::new())));
+                stack.push((commit.tree, String::new()));
+            }
+            parsing::Type::Tag => {
+                let (_, tag) = parsing::parse_tag(&object.contents)?;
+                stack.push((tag.object, String::new()));
+            }
+        }
+
+        objects.push((oid, name));
+    }
+
+    Ok(objects)
+}
+
+/// The commit HEAD points to, either through a branch or directly when detached.
+/// Returns `None` if the branch doesn't exist yet.
+pub fn resolve_head() -> crate::Result<Option<Oid>> {
+    read_ref(HEAD)
+}
+
+/// The object a ref like `HEAD` or `refs/heads/main` points to, following symbolic refs.
+/// Returns `None` if the ref, or the one it points to, doesn't exist.
+pub fn read_ref(name: &str) -> crate::Result<Option<Oid>> {
+    let mut name = name.to_owned();
+    // git gives up on symbolic refs nested this deep
+    for _ in 0..5 {
+        let Ok(contents) = fs::read_to_string(Path::new(DOT_GIT).join(&name)) else {
+            return Ok(list_refs()?
+                .into_iter()
+                .find_map(|(other, oid)| (other == name).then_some(oid)));
+        };
+        match contents.trim().strip_prefix("ref: ") {
+            Some(target) => name = target.to_owned(),
+            // files like FETCH_HEAD have more after the hash
+            None => {
+                let hash = contents.split_whitespace().next().unwrap_or_default();
+                return Ok(Some(
+                    hash.parse()
+                        .map_err(|_| Error::invalid_ref(name, "not a valid hash"))?,
+                ));
+            }
+        }
+    }
+    Err(Error::invalid_ref(
+        name,
+        "symbolic ref is nested too deeply",
+    ))
+}
+
+/// Every ref under .git/refs or in .git/packed-refs, sorted by name.
+/// Symbolic refs are skipped.
+pub fn list_refs() -> crate::Result<Vec<(String, Oid)>> {
+    let mut refs = BTreeMap::new();
+
+    if let Ok(packed_refs) = fs::read_to_string(Path::new(DOT_GIT).join(PACKED_REFS)) {
+        for line in packed_refs.lines() {
+            // skip the header and the objects that annotated tags point to
+            if line.starts_with(['#', '^']) {
+                continue;
+            }
+            let invalid = || Error::invalid_ref(line, "invalid line in packed-refs");
+            let (hash, name) = line.split_once(' ').ok_or_else(invalid)?;
+            refs.insert(name.to_owned(), hash.parse().map_err(|_| invalid())?);
+        }
+    }
+
+    // loose refs are newer than packed ones
+    for (name, path) in files_under(&Path::new(DOT_GIT).join(REFS), REFS)? {
+        if let Ok(oid) = fs::read_to_string(path)?.trim().parse() {
+            refs.insert(name, oid);
+        }
+    }
+
+    Ok(refs.into_iter().collect())
+}
+
+/// Every object recorded in the reflogs, along with the name of its ref
+pub fn reflog_entries() -> crate::Result<Vec<(String, Oid)>> {
+    let logs = Path::new(DOT_GIT).join(LOGS);
+    let mut entries = vec![];
+
+    let mut files = files_under(&logs.join(REFS), REFS)?;
+    files.push((HEAD.into(), logs.join(HEAD)));
+
+    for (name, path) in files {
+        let Ok(log) = fs::read_to_string(path) else {
+            continue;
+        };
+        for line in log.lines() {
+            // each line starts with the old and new values of the ref
+            for hash in line.split(' ').take(2) {
+                let oid: Oid = hash
+                    .parse()
+                    .map_err(|_| Error::invalid_ref(&name, "invalid line in reflog"))?;
+                if oid.as_bytes().iter().any(|&byte| byte != 0) {
+                    entries.push((name.clone(), oid));
+                }
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// The files in `dir` and its subdirectories, named with `prefix` followed by their relative path
+fn files_under(dir: &Path, prefix: &str) -> crate::Result<Vec<(String, PathBuf)>> {
+    let mut files = vec![];
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(files);
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let name = format!("{prefix}/{}", entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            files.extend(files_under(&entry.path(), &name)?);
+        } else {
+            files.push((name, entry.path()));
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Writes a file through a temporary file next to it, which is synced and then
+/// renamed into place. Readers see either the old file or all of the new one.
+pub fn write_atomic<T>(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> crate::Result<T>,
+) -> crate::Result<T> {
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    let dir = path.parent().unwrap_or(Path::new(""));
+    write_atomic_named(dir, &extension, write, |_| path.to_path_buf())
+}
+
+/// Like `write_atomic`, for files in `dir` whose names depend on what was
+/// written, like packs named after their checksum. `name` picks the file's path
+/// from what `write` returned.
+pub fn write_atomic_named<T>(
+    dir: impl AsRef<Path>,
+    extension: &str,
+    write: impl FnOnce(&mut BufWriter<File>) -> crate::Result<T>,
+    name: impl FnOnce(&T) -> PathBuf,
+) -> crate::Result<T> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    // named like git's temporary files, which nothing reads as a pack or index
+    let count = COUNTER.fetch_add(1, atomic::Ordering::Relaxed);
+    let temp_path = dir
+        .as_ref()
+        .join(format!("tmp_{extension}_{}_{count}", process::id()));
+
+    let result = (|| {
+        let mut file = BufWriter::new(File::create(&temp_path)?);
+        let written = write(&mut file)?;
+        let file = file.into_inner().map_err(io::IntoInnerError::into_error)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, name(&written))?;
+        Ok(written)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+pub fn get_config_value(section: &str, key: &str) -> crate::Result<Option<String>> {
+    config_value_in(Path::new(DOT_GIT), section, key)
+}
+
+/// Reads a value from the config of the repository whose git directory is `git_dir`
+fn config_value_in(git_dir: &Path, section: &str, key: &str) -> crate::Result<Option<String>> {
+    Ok(read_config_in(git_dir)?
         .into_iter()
         .find_map(|(s, keys_values)| (s == section).then_some(keys_values))
         .and_then(|keys_values| {
             keys_values
                 .into_iter()
-                .find_map(|(k, value)| (k == key).then_some(value))
+                .find_map(|(k, value)| k.eq_ignore_ascii_case(key).then_some(value))
         }))
 }
 
-pub fn set_config_value(section: &str, key: &str, value: String) -> anyhow::Result<()> {
+/// Parses an expiry time like git's `--expire` options take: "now", "never",
+/// a relative time such as "2.weeks.ago", a date, or "@" and a unix timestamp
+pub fn parse_expiry(expiry: &str, now: SystemTime) -> crate::Result<SystemTime> {
+    let malformed = || Error::Other(format!("malformed expiration date '{expiry}'"));
+
+    match expiry {
+        "now" | "all" => return Ok(now),
+        "never" | "false" => return Ok(UNIX_EPOCH),
+        _ => {}
+    }
+    if let Some(seconds) = expiry.strip_prefix('@') {
+        let seconds = seconds.parse().map_err(|_| malformed())?;
+        return Ok(UNIX_EPOCH + Duration::from_secs(seconds));
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(expiry, "%Y-%m-%d") {
+        let midnight = date.and_hms_opt(0, 0, 0).expect("midnight is valid");
+        return Ok(midnight.and_utc().into());
+    }
+
+    let words = expiry.split(['.', ' ']).collect::<Vec<_>>();
+    let [count, unit, "ago"] = words[..] else {
+        return Err(malformed());
+    };
+    let count: u64 = count.parse().map_err(|_| malformed())?;
+    let seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => return Err(malformed()),
+    };
+
+    Ok(now
+        .checked_sub(Duration::from_secs(count * seconds))
+        .unwrap_or(UNIX_EPOCH))
+}
+
+/// The repository's hash function, from extensions.objectFormat
+pub fn object_format() -> crate::Result<ObjectFormat> {
+    object_format_in(Path::new(DOT_GIT))
+}
+
+/// The hash function of the repository whose git directory is `git_dir`
+pub fn object_format_in(git_dir: &Path) -> crate::Result<ObjectFormat> {
+    match config_value_in(git_dir, "extensions", "objectFormat")? {
+        Some(format) => format.trim().to_ascii_lowercase().parse(),
+        None => Ok(ObjectFormat::Sha1),
+    }
+}
+
+/// Size above which files are streamed instead of read into memory,
+/// from core.bigFileThreshold. Defaults to 512 MiB, like git.
+pub fn big_file_threshold() -> crate::Result<u64> {
+    size_value("core", "bigFileThreshold", 512 * 1024 * 1024)
+}
+
+/// Bytes of decoded commits and trees to keep in memory during history and tree
+/// walks, from core.objectCacheLimit. Defaults to 96 MiB, like git's delta base cache.
+pub fn object_cache_limit() -> crate::Result<u64> {
+    size_value("core", "objectCacheLimit", 96 * 1024 * 1024)
+}
+
+/// Whether gc writes reachability bitmaps for the packs it makes, from
+/// repack.writeBitmaps. Defaults to false, like git in repositories with a work tree.
+pub fn write_bitmaps() -> crate::Result<bool> {
+    let Some(value) = get_config_value("repack", "writeBitmaps")? else {
+        return Ok(false);
+    };
+
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" | "" => Ok(false),
+        _ => Err(Error::Config(format!(
+            "bad boolean config value '{value}' for 'repack.writeBitmaps'"
+        ))),
+    }
+}
+
+/// A size in bytes, optionally followed by a k, m or g suffix
+fn size_value(section: &str, key: &str, default: u64) -> crate::Result<u64> {
+    let Some(value) = get_config_value(section, key)? else {
+        return Ok(default);
+    };
+
+    let value = value.trim().to_ascii_lowercase();
+    let (digits, scale) = [("k", 1 << 10), ("m", 1 << 20), ("g", 1 << 30)]
+        .into_iter()
+        .find_map(|(suffix, scale)| Some((value.strip_suffix(suffix)?, scale)))
+        .unwrap_or((&value, 1));
+    let size = digits
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| Error::Config(format!("invalid {section}.{key}: {value}")))?;
+
+    Ok(size * scale)
+}
+
+/// Compression level for loose objects, from core.looseCompression
+/// or core.compression
+pub fn loose_compression() -> crate::Result<Compression> {
+    compression_level(&[("core", "looseCompression"), ("core", "compression")])
+}
+
+/// Compression level for packs, from pack.compression or core.compression
+pub fn pack_compression() -> crate::Result<Compression> {
+    compression_level(&[("pack", "compression"), ("core", "compression")])
+}
+
+/// The first of `keys` that is set, where -1 (or nothing) is zlib's default
+/// level, 0 stores objects uncompressed and 9 compresses the most
+fn compression_level(keys: &[(&str, &str)]) -> crate::Result<Compression> {
+    for (section, key) in keys {
+        let Some(value) = get_config_value(section, key)? else {
+            continue;
+        };
+
+        let level = value
+            .trim()
+            .parse::<i32>()
+            .ok()
+            .filter(|level| (-1..=9).contains(level))
+            .ok_or_else(|| {
+                Error::Config(format!(
+                    "bad zlib compression level {value} for {section}.{key}"
+                ))
+            })?;
+
+        return Ok(match u32::try_from(level) {
+            Ok(level) => Compression::new(level),
+            Err(_) => Compression::default(),
+        });
+    }
+
+    Ok(Compression::default())
+}
+
+pub fn set_config_value(section: &str, key: &str, value: String) -> crate::Result<()> {
     let mut config = read_config()?;
 
     let search_result = config
@@ -196,15 +554,13 @@ pub fn set_config_value(section: &str, key: &str, value: String) -> anyhow::Resu
     write_config(config)
 }
 
-pub fn list_config() -> anyhow::Result<String> {
-    use std::fmt::Write; // prevent conflict with io::Write
-
+pub fn list_config() -> crate::Result<String> {
     let mut list = String::new();
     for (section, keys_values) in read_config()? {
         let section = section.trim_end_matches('"').replace(" \"", ".");
 
         for (key, value) in keys_values {
-            writeln!(&mut list, "{section}.{key}={value}")?;
+            list.push_str(&format!("{section}.{key}={value}\n"));
         }
     }
 
@@ -213,8 +569,12 @@ pub fn list_config() -> anyhow::Result<String> {
 
 type Section = (String, Vec<(String, String)>);
 
-fn read_config() -> anyhow::Result<Vec<Section>> {
-    let Ok(config) = fs::read_to_string(Path::new(DOT_GIT).join(CONFIG)) else {
+fn read_config() -> crate::Result<Vec<Section>> {
+    read_config_in(Path::new(DOT_GIT))
+}
+
+fn read_config_in(git_dir: &Path) -> crate::Result<Vec<Section>> {
+    let Ok(config) = fs::read_to_string(git_dir.join(CONFIG)) else {
         return Ok(vec![]);
     };
 
@@ -229,7 +589,9 @@ fn read_config() -> anyhow::Result<Vec<Section>> {
                 sections.push((prev_section_name.into(), mem::take(&mut section)));
             }
         } else if !line.is_empty() {
-            let (key, value) = line.split_once(" = ").context("invalid line in config")?;
+            let (key, value) = line
+                .split_once(" = ")
+                .ok_or_else(|| Error::Config(format!("invalid line in config: {line}")))?;
             section.push((key.into(), value.into()));
         }
     }
@@ -240,7 +602,7 @@ fn read_config() -> anyhow::Result<Vec<Section>> {
     Ok(sections)
 }
 
-fn write_config(config: Vec<Section>) -> anyhow::Result<()> {
+fn write_config(config: Vec<Section>) -> crate::Result<()> {
     let mut config_file = File::create(Path::new(DOT_GIT).join(CONFIG))?;
 
     for (section, keys_values) in config {
@@ -262,9 +624,9 @@ mod tests {
 
     use super::*;
 
-    const CONFIG_TEST_DIR: &'static str = "config_test_dir";
+    const CONFIG_TEST_DIR: &str = "config_test_dir";
 
-    struct Setup(MutexGuard<'static, ()>);
+    struct Setup(#[allow(dead_code)] MutexGuard<'static, ()>);
     impl Setup {
         fn init() -> Self {
             let guard = FORCE_SINGLE_THREAD.lock().unwrap();
@@ -342,6 +704,11 @@ mod tests {
             get_config_value("user", "name").unwrap().unwrap(),
             "Andres Dejesus"
         );
+
+        assert_eq!(
+            get_config_value("core", "fileMode").unwrap().unwrap(),
+            "true"
+        );
     }
 
     #[test]
@@ -395,4 +762,22 @@ mod tests {
 "
         );
     }
+
+    #[test]
+    fn write_files_atomically() {
+        let _setup = Setup::init();
+        let path = Path::new(".git/test.pack");
+
+        write_atomic(path, |file| Ok(file.write_all(b"first")?)).unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"first");
+
+        // a failed write leaves the old file, and no temporary file behind
+        let result: crate::Result<()> = write_atomic(path, |file| {
+            file.write_all(b"second")?;
+            crate::bail!("failed partway")
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read(path).unwrap(), b"first");
+        assert_eq!(fs::read_dir(".git").unwrap().count(), 2);
+    }
 }